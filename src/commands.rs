@@ -1,9 +1,28 @@
-use anyhow::Result;
-use clap::{arg, command, Parser, Subcommand};
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use clap::{Parser, Subcommand, ValueEnum};
 use crossterm::{cursor, execute, style::Color, terminal::ClearType};
+use itertools::Itertools;
+use regex::Regex;
 use tabled::Table;
 
-use crate::{terminal::eprintln_colored, terminal::println, util::table_settings, Context};
+use crate::{
+    input::{PromptKind, SESSION_VALUE_NAME},
+    script::OnError,
+    session::SessionSelection,
+    terminal::eprintln_colored,
+    terminal::println,
+    util::table_settings,
+    variables::{self, Value},
+    Context,
+};
+
+mod alias;
+mod broadcast;
+mod log;
+mod recording;
+use self::recording::replay;
 
 // TODO: https://docs.rs/clap/latest/clap/_cookbook/repl/index.html
 
@@ -16,59 +35,313 @@ pub struct Cli {
 
 #[derive(Debug, Subcommand)]
 pub enum Commands {
+    /// List, show or define aliases, e.g. `alias web = session send @web`
+    ///
+    /// `$1`, `$2`, ... in the command are replaced with the alias's arguments and `$@` with all of
+    /// them. Without any of these, the arguments are appended.
+    Alias {
+        /// The alias to show or define
+        name: Option<String>,
+        /// `=` followed by the command to run
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, requires = "name")]
+        definition: Vec<String>,
+    },
+    /// Send the same line to several sessions, e.g. `broadcast 0 web id` or `broadcast --all id`
+    Broadcast {
+        /// Sessions to send to, followed by the line to send
+        ///
+        /// The line can use variables, expressions and escapes like `printf`.
+        #[arg(value_name = SESSION_VALUE_NAME, required = true)]
+        args: Vec<String>,
+        /// Send to every connected session
+        #[arg(short, long)]
+        all: bool,
+        /// Wait for each session's response and show them in a table
+        #[arg(short, long)]
+        collect: bool,
+        /// How long a session must be quiet for its response to be complete, in milliseconds, if
+        /// its shell prompt is not known yet
+        #[arg(short, long, default_value_t = 500, requires = "collect")]
+        wait: u64,
+    },
     /// Clear the terminal
     Clear,
     /// Exit the program
     #[command(aliases = ["quit", "q"])]
     Exit,
+    /// List, search or clear this prompt's command history
+    History {
+        #[command(subcommand)]
+        command: Option<HistoryCommands>,
+    },
+    /// Turn session transcripts on or off, or show where they are written
+    Log {
+        /// Whether to log, for all sessions including new ones unless a session is given
+        #[arg(value_enum)]
+        state: Option<Toggle>,
+        #[arg(value_name = SESSION_VALUE_NAME, requires = "state")]
+        session: Option<SessionSelection>,
+    },
+    /// Start or stop an asciicast recording of sessions, or show the recordings in progress
+    Record {
+        #[arg(value_enum, requires = "session")]
+        state: Option<Toggle>,
+        #[arg(value_name = SESSION_VALUE_NAME)]
+        session: Option<SessionSelection>,
+    },
+    /// Play back an asciicast recording; press Esc or Ctrl-C to stop
+    Replay {
+        /// The `.cast` file to play
+        file: PathBuf,
+        /// Playback speed, e.g. 2 for twice as fast
+        #[arg(short, long, default_value_t = 1.0)]
+        speed: f64,
+    },
+    /// List or run rhai scripts
+    ///
+    /// Scripts in the `scripts` directory of the config directory can also be run by name, e.g.
+    /// `exploit 10.0.0.5` for `scripts/exploit.rhai`.
+    Script {
+        #[command(subcommand)]
+        command: Option<ScriptCommands>,
+    },
+    /// Run rctf and termcraft commands from a file, one per line
+    ///
+    /// `ssh` connects in the background and `session <session>` picks the session that
+    /// termcraft commands like `expect` and `sendline` apply to.
+    Source {
+        /// The script to run
+        file: PathBuf,
+        /// Whether to stop or carry on when a command fails
+        #[arg(long, value_enum, default_value_t = OnError::Stop)]
+        on_error: OnError,
+    },
+    /// Remove an alias
+    Unalias {
+        /// The name of the alias
+        name: String,
+    },
     /// Get or modify variables
-    // TODO: get, set, and remove as subcommands (https://docs.rs/clap/latest/clap/_derive/_cookbook/git/index.html)
     Var {
+        #[command(subcommand)]
+        command: Option<VarCommands>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Toggle {
+    On,
+    Off,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum HistoryCommands {
+    /// List history entries
+    #[command(alias = "ls")]
+    List {
+        /// Only show the most recent entries
+        #[arg(short = 'n', long)]
+        count: Option<usize>,
+    },
+    /// Search history entries with a regex
+    Grep {
+        /// The regex to search for
+        pattern: String,
+    },
+    /// Remove all history entries
+    Clear,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ScriptCommands {
+    /// List the scripts that can be run by name
+    #[command(alias = "ls")]
+    List,
+    /// Run a rhai script, which sees its arguments as `ARGS` and the termcraft session as `SESSION`
+    Run {
+        /// The script to run
+        file: PathBuf,
+        /// Arguments for the script
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum VarCommands {
+    /// List all variables
+    #[command(alias = "ls")]
+    List,
+    /// Get the value of a variable
+    Get {
         /// The name of the variable
-        name: Option<String>,
+        name: String,
+    },
+    /// Set a variable
+    Set {
+        /// The name of the variable
+        name: String,
         /// The value to set the variable to
-        #[arg(requires = "name")]
-        value: Option<String>,
+        value: String,
+        /// Evaluate the value as an expression, e.g. `base + 0x18` or `b'A' * 40 + p64(addr)`
+        #[arg(short, long)]
+        expr: bool,
+    },
+    /// Remove a variable
+    #[command(alias = "rm")]
+    Remove {
+        /// The name of the variable
+        name: String,
     },
 }
 
 impl<'a> Context<'a> {
-    pub async fn handle_command(&mut self, command: Commands) -> Result<()> {
+    /// Run a command that any prompt has. `session_index` is the session termcraft is open on,
+    /// if any.
+    pub async fn handle_command(
+        &mut self,
+        command: Commands,
+        kind: PromptKind,
+        session_index: Option<usize>,
+    ) -> Result<()> {
         match command {
+            Commands::Alias { name, definition } => self.alias(name, definition)?,
+            Commands::Broadcast {
+                args,
+                all,
+                collect,
+                wait,
+            } => self.broadcast(args, all, collect, wait).await?,
             Commands::Clear => execute!(
                 std::io::stdout(),
                 crossterm::terminal::Clear(ClearType::All),
                 cursor::MoveTo(0, 0)
             )?,
-            Commands::Var { name, value } => self.variable(name, value).await?,
+            Commands::History { command } => self.history_command(
+                command.unwrap_or(HistoryCommands::List { count: None }),
+                kind,
+            )?,
+            Commands::Log { state, session } => self.log(state, session)?,
+            Commands::Record { state, session } => self.record(state, session)?,
+            Commands::Replay { file, speed } => replay(&file, speed).await?,
+            Commands::Script { command } => match command.unwrap_or(ScriptCommands::List) {
+                ScriptCommands::List => self.list_scripts()?,
+                ScriptCommands::Run { file, args } => {
+                    self.run_rhai(&file, args, session_index).await?;
+                }
+            },
+            Commands::Source { file, on_error } => {
+                self.run_script(&file, on_error, session_index).await?;
+            }
+            Commands::Unalias { name } => self.unalias(&name)?,
+            Commands::Var { command } => self.variable(command.unwrap_or(VarCommands::List))?,
             Commands::Exit => {}
         };
 
         Ok(())
     }
 
-    async fn variable(&mut self, name: Option<String>, value: Option<String>) -> Result<()> {
-        if let Some(name) = name {
-            if let Some(value) = value {
-                self.variables.insert(name.clone(), value);
+    /// Run a line as if it had been entered at the rctf prompt, for commands that any prompt has.
+    pub(crate) async fn run_line(&mut self, line: &str) -> Result<()> {
+        let command = parse_command(line)?;
+        self.handle_command(command, PromptKind::Rctf, None).await
+    }
+
+    fn history_command(&mut self, command: HistoryCommands, kind: PromptKind) -> Result<()> {
+        let history = self.history(kind);
+        let entries: Vec<_> = match command {
+            HistoryCommands::List { count } => {
+                let skip = count.map_or(0, |count| history.len().saturating_sub(count));
+                history.iter().enumerate().skip(skip).collect()
+            }
+            HistoryCommands::Grep { pattern } => {
+                let regex = Regex::new(&pattern)?;
+                history
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, entry)| regex.is_match(&entry.line))
+                    .collect()
+            }
+            HistoryCommands::Clear => {
+                self.history_mut(kind).clear();
+                return Ok(());
             }
-            println(
-                self.variables
-                    .get(&name)
-                    .unwrap_or(&format!("Variable `{name}` is currently unset.")),
-            )?;
-        } else {
-            if self.variables.is_empty() {
-                eprintln_colored("There are currently no variables.", Color::Red)?;
-            } else {
-                let table = Table::builder(&self.variables);
-                // TODO: test
-                // builder.set_header(["name", "value"]);
+        };
+
+        if entries.is_empty() {
+            eprintln_colored("There are no matching history entries.", Color::Red)?;
+            return Ok(());
+        }
+
+        let mut table = Table::builder(entries.into_iter().map(|(i, entry)| {
+            let timestamp = entry
+                .timestamp
+                .map(|timestamp| timestamp.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_default();
+            (
+                i,
+                timestamp,
+                entry.session.clone().unwrap_or_default(),
+                entry.line.clone(),
+            )
+        }));
+        table.set_header(["index", "time", "session", "command"]);
+
+        let table = table.build().with(table_settings()).to_string();
+        println(table)?;
+
+        Ok(())
+    }
+
+    fn variable(&mut self, command: VarCommands) -> Result<()> {
+        match command {
+            VarCommands::List => {
+                if self.variables.is_empty() {
+                    eprintln_colored("There are currently no variables.", Color::Red)?;
+                    return Ok(());
+                }
+
+                let mut table = Table::builder(
+                    self.variables
+                        .iter()
+                        .sorted_by_key(|(name, _)| *name)
+                        .map(|(name, value)| (name, value.type_name(), value.to_string())),
+                );
+                table.set_header(["name", "type", "value"]);
 
                 let table = table.build().with(table_settings()).to_string();
                 println(table)?;
             }
+            VarCommands::Get { name } => {
+                let Some(value) = self.variables.get(&name) else {
+                    bail!("Variable `{name}` is currently unset.");
+                };
+                println(value)?;
+            }
+            VarCommands::Set { name, value, expr } => {
+                let value = if expr {
+                    variables::expr::evaluate(&value, &self.variables)?
+                } else {
+                    Value::Str(value)
+                };
+                println(&value)?;
+                self.variables.insert(name, value);
+            }
+            VarCommands::Remove { name } => {
+                if self.variables.remove(&name).is_none() {
+                    bail!("Variable `{name}` is currently unset.");
+                }
+            }
         }
+
         Ok(())
     }
 }
+
+/// Parse a line into one of the commands that any prompt has.
+pub(crate) fn parse_command(line: &str) -> Result<Commands> {
+    let Some(args) = shlex::split(line) else {
+        bail!("Invalid quoting.");
+    };
+    Ok(Cli::try_parse_from(args)?.command)
+}
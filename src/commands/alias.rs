@@ -0,0 +1,98 @@
+use anyhow::{bail, Result};
+use tabled::Table;
+
+use crate::{
+    input::{check, expand},
+    terminal::println,
+    util::table_settings,
+    Context,
+};
+
+impl<'a> Context<'a> {
+    pub(super) fn alias(&mut self, name: Option<String>, definition: Vec<String>) -> Result<()> {
+        let Some(name) = name else {
+            return self.list_aliases();
+        };
+        let Some((equals, words)) = definition.split_first() else {
+            let Some(template) = self.alias_template(&name) else {
+                bail!("Alias `{name}` is currently unset.");
+            };
+            println(format!("alias {name} = {template}"))?;
+            return Ok(());
+        };
+        if equals != "=" || words.is_empty() {
+            bail!("Use `alias {name} = COMMAND` to define an alias.");
+        }
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            bail!("Alias names can't be empty or contain spaces.");
+        }
+        if self.settings.aliases.contains_key(&name) {
+            bail!("Alias `{name}` is set in the config file, which takes precedence.");
+        }
+
+        // A quoted command is kept as it was typed.
+        let template = match words {
+            [template] => template.clone(),
+            words => shlex::try_join(words.iter().map(String::as_str))?,
+        };
+        check(&template)?;
+        self.aliases.set(name, template);
+
+        Ok(())
+    }
+
+    pub(super) fn unalias(&mut self, name: &str) -> Result<()> {
+        if self.aliases.remove(name).is_some() {
+            return Ok(());
+        }
+        if self.settings.aliases.contains_key(name) {
+            bail!("Alias `{name}` is set in the config file, remove it from there instead.");
+        }
+        bail!("Alias `{name}` is currently unset.");
+    }
+
+    /// Replace an alias at the start of a command with what it stands for.
+    pub(crate) fn expand_alias(&self, args: Vec<String>) -> Result<Vec<String>> {
+        let Some(template) = args.first().and_then(|name| self.alias_template(name)) else {
+            return Ok(args);
+        };
+        expand(template, &args[1..])
+    }
+
+    /// The command an alias stands for, with the config file taking precedence.
+    fn alias_template(&self, name: &str) -> Option<&str> {
+        self.settings
+            .aliases
+            .get(name)
+            .map(String::as_str)
+            .or_else(|| self.aliases.get(name))
+    }
+
+    fn list_aliases(&self) -> Result<()> {
+        let mut aliases: Vec<_> = self
+            .aliases
+            .iter()
+            .filter(|(name, _)| !self.settings.aliases.contains_key(*name))
+            .map(|(name, template)| [name.clone(), template.clone(), "alias".to_owned()])
+            .collect();
+        aliases.extend(
+            self.settings
+                .aliases
+                .iter()
+                .map(|(name, template)| [name.clone(), template.clone(), "config".to_owned()]),
+        );
+        if aliases.is_empty() {
+            println("There are currently no aliases.")?;
+            return Ok(());
+        }
+        aliases.sort();
+
+        let mut table = Table::builder(aliases);
+        table.set_header(["name", "command", "source"]);
+
+        let table = table.build().with(table_settings()).to_string();
+        println(table)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use crossterm::style::Color;
+use futures::future::join_all;
+use tabled::Table;
+use tokio::{
+    sync::watch,
+    time::{timeout, Instant},
+};
+
+use crate::{
+    session::{wait_for_prompt, OutputBuffer, SessionSelection, SessionState},
+    terminal::{eprintln_colored, println, strip_escapes},
+    util::table_settings,
+    Context,
+};
+
+/// The longest to wait for a session to respond when collecting responses.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl<'a> Context<'a> {
+    pub(super) async fn broadcast(
+        &mut self,
+        mut args: Vec<String>,
+        all: bool,
+        collect: bool,
+        wait: u64,
+    ) -> Result<()> {
+        let text = args.pop().unwrap_or_default();
+        let indices = if all {
+            if !args.is_empty() {
+                bail!("Sessions can't be given along with --all.");
+            }
+            self.sessions
+                .iter()
+                .enumerate()
+                .filter(|(_, session)| {
+                    session
+                        .as_ref()
+                        .is_some_and(|session| session.state() == SessionState::Connected)
+                })
+                .map(|(index, _)| index)
+                .collect()
+        } else {
+            if args.is_empty() {
+                bail!("Give the sessions to send to, or --all.");
+            }
+            let mut indices = Vec::new();
+            for session in &args {
+                for index in self.select_sessions(&session.parse::<SessionSelection>()?)? {
+                    if !indices.contains(&index) {
+                        indices.push(index);
+                    }
+                }
+            }
+            indices
+        };
+        if indices.is_empty() {
+            bail!("There are no sessions to send to.");
+        }
+
+        let data = [self.parse_line(&text)?, b"\n".to_vec()].concat();
+        let mut receivers = Vec::new();
+        let mut failures = Vec::new();
+        for &index in &indices {
+            let session = self.sessions.get_mut(index).unwrap();
+            let rx_output = session.output();
+            let start = rx_output.borrow().written();
+            let prompt = session.prompt(&self.settings.session);
+            // One session failing shouldn't keep the command from the rest.
+            match session.send(&data).await {
+                Ok(()) => receivers.push((index, rx_output, start, prompt)),
+                Err(e) => failures.push((index, format!("Failed to send: {e}"))),
+            }
+        }
+
+        if !collect {
+            for (index, e) in &failures {
+                eprintln_colored(format!("{}: {e}", self.session_label(*index)), Color::Red)?;
+            }
+            println(format!("Sent to {} sessions.", receivers.len()))?;
+            return Ok(());
+        }
+
+        let quiet = Duration::from_millis(wait);
+        let responses = join_all(receivers.into_iter().map(
+            |(index, mut rx_output, start, prompt)| async move {
+                // The response is complete once the shell prompts again, if its prompt is known.
+                match prompt {
+                    Some(prompt) => {
+                        wait_for_prompt(&mut rx_output, start, Some(&prompt), RESPONSE_TIMEOUT)
+                            .await;
+                    }
+                    None => wait_for_quiet(&mut rx_output, quiet).await,
+                }
+                let output = rx_output.borrow().since(start);
+                (index, response(&output))
+            },
+        ))
+        .await;
+        let mut responses: Vec<_> = responses.into_iter().chain(failures).collect();
+        responses.sort_by_key(|(index, _)| indices.iter().position(|i| i == index));
+
+        let mut table = Table::builder(
+            responses
+                .into_iter()
+                .map(|(index, response)| [self.session_label(index), response]),
+        );
+        table.set_header(["session", "response"]);
+
+        let table = table.build().with(table_settings()).to_string();
+        println(table)?;
+
+        Ok(())
+    }
+}
+
+/// Wait until a session has stopped printing for `quiet`, or for [`RESPONSE_TIMEOUT`].
+async fn wait_for_quiet(rx_output: &mut watch::Receiver<OutputBuffer>, quiet: Duration) {
+    let deadline = Instant::now() + RESPONSE_TIMEOUT;
+    while Instant::now() < deadline {
+        match timeout(quiet, rx_output.changed()).await {
+            Ok(Ok(())) => continue,
+            Ok(Err(_)) | Err(_) => break,
+        }
+    }
+}
+
+/// The complete lines of output after the echoed command, leaving out the next prompt.
+fn response(output: &[u8]) -> String {
+    let text = String::from_utf8_lossy(&strip_escapes(output)).into_owned();
+    let Some(end) = text.rfind('\n') else {
+        return String::new();
+    };
+    text[..end].lines().skip(1).collect::<Vec<_>>().join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn responses() {
+        assert_eq!(
+            response(b"id\r\nuid=0(root) gid=0(root)\r\n\x1b[32mroot@web\x1b[0m:~# "),
+            "uid=0(root) gid=0(root)"
+        );
+        assert_eq!(response(b"ls\r\na\r\nb\r\n$ "), "a\nb");
+        assert_eq!(response(b"sleep 5"), "");
+    }
+}
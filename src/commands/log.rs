@@ -0,0 +1,111 @@
+use anyhow::{bail, Result};
+use chrono::Local;
+use tabled::Table;
+
+use super::Toggle;
+use crate::{files, session::SessionSelection, terminal::println, util::table_settings, Context};
+
+/// Subdirectory of the data directory that transcripts are written to.
+const TRANSCRIPTS_DIR: &str = "transcripts";
+
+impl<'a> Context<'a> {
+    pub(super) fn log(
+        &mut self,
+        state: Option<Toggle>,
+        session: Option<SessionSelection>,
+    ) -> Result<()> {
+        let Some(state) = state else {
+            return self.list_transcripts();
+        };
+        let enabled = state == Toggle::On;
+
+        let indices = match session {
+            Some(session) => self.select_sessions(&session)?,
+            None => {
+                self.settings.log.enabled = enabled;
+                self.sessions
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, session)| session.is_some())
+                    .map(|(index, _)| index)
+                    .collect()
+            }
+        };
+        for index in indices {
+            self.set_logging(index, enabled)?;
+        }
+
+        Ok(())
+    }
+
+    /// Start or stop writing a session's transcript. A session that is logged again continues
+    /// its previous transcript.
+    pub(crate) fn set_logging(&mut self, session_index: usize, enabled: bool) -> Result<()> {
+        let (Some(session), Some(handle)) = (
+            self.sessions.get(session_index),
+            self.sessions.handle(session_index),
+        ) else {
+            bail!("No session found with index {session_index}.");
+        };
+        let transcript = session.transcript();
+        if !enabled {
+            transcript.stop();
+            return Ok(());
+        }
+
+        let path = match transcript.path() {
+            Some(path) => path,
+            None => {
+                // The handle rather than the index, which a new session may reuse straight away.
+                let filename = format!("{}-{handle}.log", Local::now().format("%Y%m%d-%H%M%S"));
+                let Some(path) = files::data::create_path(format!("{TRANSCRIPTS_DIR}/{filename}"))
+                else {
+                    bail!("Failed to create the transcripts directory.");
+                };
+                path
+            }
+        };
+        let description = format!(
+            "session {} ({})",
+            self.session_label(session_index),
+            session.remote()
+        );
+        transcript.start(&path, &description)
+    }
+
+    fn list_transcripts(&self) -> Result<()> {
+        let state = if self.settings.log.enabled {
+            "on"
+        } else {
+            "off"
+        };
+        println(format!("Logging of new sessions is {state}."))?;
+        if self.sessions.iter().all(|session| session.is_none()) {
+            return Ok(());
+        }
+
+        let mut table = Table::builder(
+            self.sessions
+                .iter()
+                .enumerate()
+                .filter_map(|(index, session)| Some((index, session.as_deref()?)))
+                .map(|(index, session)| {
+                    let transcript = session.transcript();
+                    [
+                        self.session_label(index),
+                        if transcript.is_logging() { "on" } else { "off" }.to_owned(),
+                        transcript
+                            .path()
+                            .map(|path| path.display().to_string())
+                            .unwrap_or_default(),
+                    ]
+                }),
+        );
+        table.set_header(["session", "logging", "transcript"]);
+
+        let table = table.build().with(table_settings()).to_string();
+        println(table)?;
+
+        Ok(())
+    }
+}
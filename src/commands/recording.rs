@@ -0,0 +1,208 @@
+use std::{
+    io::Write,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context as _, Result};
+use chrono::Local;
+use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyModifiers};
+use futures::StreamExt;
+use tabled::Table;
+use tokio::{select, time::sleep_until};
+
+use super::Toggle;
+use crate::{
+    files,
+    session::{CastEvent, CastHeader, SessionSelection},
+    terminal::println,
+    util::table_settings,
+    Context,
+};
+
+/// Subdirectory of the data directory that recordings are written to.
+const RECORDINGS_DIR: &str = "recordings";
+
+impl<'a> Context<'a> {
+    pub(super) fn record(
+        &mut self,
+        state: Option<Toggle>,
+        session: Option<SessionSelection>,
+    ) -> Result<()> {
+        let (Some(state), Some(session)) = (state, session) else {
+            return self.list_recordings();
+        };
+
+        let size = crossterm::terminal::size()?;
+        for index in self.select_sessions(&session)? {
+            let label = self.session_label(index);
+            let session = self.sessions.get(index).unwrap();
+            let recording = session.recording();
+            match state {
+                Toggle::On => {
+                    if recording.path().is_some() {
+                        continue;
+                    }
+                    // The handle rather than the index, which a new session may reuse straight away.
+                    let handle = self.sessions.handle(index).unwrap();
+                    let filename =
+                        format!("{}-{handle}.cast", Local::now().format("%Y%m%d-%H%M%S"));
+                    let Some(path) =
+                        files::data::create_path(format!("{RECORDINGS_DIR}/{filename}"))
+                    else {
+                        bail!("Failed to create the recordings directory.");
+                    };
+                    let title = format!("{label} ({})", session.remote());
+                    recording.start(&path, size, &title)?;
+                    println(format!("Recording {label} to {}.", path.display()))?;
+                }
+                Toggle::Off => {
+                    if let Some(path) = recording.stop() {
+                        println(format!("Saved recording of {label} to {}.", path.display()))?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn list_recordings(&self) -> Result<()> {
+        let recordings: Vec<_> = self
+            .sessions
+            .iter()
+            .enumerate()
+            .filter_map(|(index, session)| {
+                let path = session.as_ref()?.recording().path()?;
+                Some([self.session_label(index), path.display().to_string()])
+            })
+            .collect();
+        if recordings.is_empty() {
+            println("No sessions are being recorded.")?;
+            return Ok(());
+        }
+
+        let mut table = Table::builder(recordings);
+        table.set_header(["session", "recording"]);
+
+        let table = table.build().with(table_settings()).to_string();
+        println(table)?;
+
+        Ok(())
+    }
+}
+
+/// Play back the output of an asciicast v2 recording.
+pub(super) async fn replay(path: &Path, speed: f64) -> Result<()> {
+    if !(speed > 0.0 && speed.is_finite()) {
+        bail!("The speed must be a positive number.");
+    }
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}.", path.display()))?;
+    let (header, events) = parse_cast(&text)?;
+    if let Some(title) = &header.title {
+        println(format!("Replaying {title}."))?;
+    }
+
+    let mut stdout = std::io::stdout();
+    let mut reader = EventStream::new();
+    let start = Instant::now();
+    for (time, kind, data) in events {
+        if kind != "o" {
+            continue;
+        }
+
+        let Some(at) = Duration::try_from_secs_f64(time.max(0.0) / speed)
+            .ok()
+            .and_then(|offset| start.checked_add(offset))
+        else {
+            bail!("The recording is too long to replay at this speed.");
+        };
+        loop {
+            select! {
+                _ = sleep_until(at.into()) => break,
+                event = reader.next() => match event {
+                    Some(Ok(Event::Key(KeyEvent { code: KeyCode::Esc, .. })))
+                    | Some(Ok(Event::Key(KeyEvent {
+                        code: KeyCode::Char('c'),
+                        modifiers: KeyModifiers::CONTROL,
+                        ..
+                    }))) => {
+                        println("\r\nReplay stopped.")?;
+                        return Ok(());
+                    }
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => return Err(e.into()),
+                    None => bail!("Out of events."),
+                },
+            }
+        }
+        stdout.write_all(data.as_bytes())?;
+        stdout.flush()?;
+    }
+    println("\r\nReplay finished.")?;
+
+    Ok(())
+}
+
+fn parse_cast(text: &str) -> Result<(CastHeader, Vec<CastEvent>)> {
+    let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+    let Some(header) = lines.next() else {
+        bail!("The recording is empty.");
+    };
+    let header: CastHeader =
+        serde_json::from_str(header).context("The recording has no asciicast header.")?;
+    if header.version != 2 {
+        bail!(
+            "Only asciicast version 2 is supported, not {}.",
+            header.version
+        );
+    }
+
+    let events = lines
+        .enumerate()
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("Invalid event on line {} of the recording.", i + 2))
+        })
+        .collect::<Result<_>>()?;
+    Ok((header, events))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let (header, events) = parse_cast(concat!(
+            r#"{"version": 2, "width": 80, "height": 24, "title": "web"}"#,
+            "\n",
+            r#"[0.5, "o", "$ id\r\n"]"#,
+            "\n",
+            r#"[1.25, "i", "x"]"#,
+            "\n",
+        ))
+        .unwrap();
+        assert_eq!(
+            header,
+            CastHeader {
+                version: 2,
+                width: 80,
+                height: 24,
+                timestamp: None,
+                title: Some("web".to_string()),
+            }
+        );
+        assert_eq!(
+            events,
+            [
+                (0.5, "o".to_string(), "$ id\r\n".to_string()),
+                (1.25, "i".to_string(), "x".to_string()),
+            ]
+        );
+
+        assert!(parse_cast(r#"{"version": 1, "width": 80, "height": 24}"#).is_err());
+        assert!(parse_cast("").is_err());
+    }
+}
@@ -40,14 +40,46 @@ pub mod cache {
     }
 
     fn create_path(filename: &str) -> Option<PathBuf> {
-        let Some(dir) =
-            ProjectDirs::from("", "", env!("CARGO_PKG_NAME")).map(|dir| dir.cache_dir().to_owned())
-        else {
-            return None;
-        };
+        let dir = ProjectDirs::from("", "", env!("CARGO_PKG_NAME"))
+            .map(|dir| dir.cache_dir().to_owned())?;
+        match fs::create_dir_all(&dir) {
+            Ok(_) => Some(dir.join(filename)),
+            Err(_) => None,
+        }
+    }
+}
+
+pub mod config {
+    use std::{fs, path::PathBuf};
+
+    use directories::ProjectDirs;
+
+    pub fn create_path(filename: &str) -> Option<PathBuf> {
+        let dir = ProjectDirs::from("", "", env!("CARGO_PKG_NAME"))
+            .map(|dir| dir.config_dir().to_owned())?;
         match fs::create_dir_all(&dir) {
             Ok(_) => Some(dir.join(filename)),
             Err(_) => None,
         }
     }
 }
+
+pub mod data {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    use directories::ProjectDirs;
+
+    /// Path to a file in the data directory, which may be in a subdirectory, e.g.
+    /// `transcripts/1.log`.
+    pub fn create_path(filename: impl AsRef<Path>) -> Option<PathBuf> {
+        let path = ProjectDirs::from("", "", env!("CARGO_PKG_NAME"))
+            .map(|dir| dir.data_dir().join(filename))?;
+        match fs::create_dir_all(path.parent()?) {
+            Ok(_) => Some(path),
+            Err(_) => None,
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::settings::HistorySettings;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub line: String,
+    /// Unknown for entries migrated from the old history format.
+    pub timestamp: Option<DateTime<Local>>,
+    /// The session the line was run in, if any.
+    pub session: Option<String>,
+}
+
+/// Entries as stored on disk, which used to be bare strings.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredEntry {
+    Line(String),
+    Entry(HistoryEntry),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "Vec<StoredEntry>")]
+pub struct CommandHistory(VecDeque<HistoryEntry>);
+
+impl CommandHistory {
+    /// Record a line, unless the settings say it should be skipped.
+    pub fn push(&mut self, line: &str, session: Option<String>, settings: &HistorySettings) {
+        if line.trim().is_empty()
+            || (settings.ignore_space && line.starts_with(' '))
+            || (settings.ignore_dups && self.0.back().is_some_and(|last| last.line == line))
+        {
+            return;
+        }
+
+        self.0.push_back(HistoryEntry {
+            line: line.to_owned(),
+            timestamp: Some(Local::now()),
+            session,
+        });
+        while self.0.len() > settings.size {
+            self.0.pop_front();
+        }
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.0.iter().map(|entry| entry.line.clone()).collect()
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &HistoryEntry> + ExactSizeIterator {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+impl From<Vec<StoredEntry>> for CommandHistory {
+    fn from(entries: Vec<StoredEntry>) -> Self {
+        Self(
+            entries
+                .into_iter()
+                .map(|entry| match entry {
+                    StoredEntry::Line(line) => HistoryEntry {
+                        line,
+                        timestamp: None,
+                        session: None,
+                    },
+                    StoredEntry::Entry(entry) => entry,
+                })
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push() {
+        let settings = HistorySettings {
+            size: 2,
+            ..Default::default()
+        };
+        let mut history = CommandHistory::default();
+
+        history.push("session", None, &settings);
+        history.push("session", None, &settings);
+        history.push(" var set password hunter2", None, &settings);
+        history.push("", None, &settings);
+        assert_eq!(history.lines(), ["session"]);
+
+        history.push("printf id", Some("web".to_string()), &settings);
+        history.push("bg", Some("web".to_string()), &settings);
+        assert_eq!(history.lines(), ["printf id", "bg"]);
+        assert_eq!(
+            history.iter().last().unwrap().session.as_deref(),
+            Some("web")
+        );
+    }
+
+    #[test]
+    fn migrate() {
+        let history: CommandHistory =
+            serde_json::from_str(r#"["ssh root 10.0.0.1", "session"]"#).unwrap();
+        assert_eq!(history.lines(), ["ssh root 10.0.0.1", "session"]);
+        assert!(history.iter().all(|entry| entry.timestamp.is_none()));
+
+        let json = serde_json::to_string(&history).unwrap();
+        let roundtrip: CommandHistory = serde_json::from_str(&json).unwrap();
+        assert_eq!(roundtrip, history);
+    }
+}
@@ -1,31 +1,103 @@
-use std::io::{self, Write};
+use std::{
+    io::{self, Write},
+    ops::Range,
+};
+
+use crate::{
+    script::user_script,
+    session::{FiredTrigger, Notifier},
+    terminal::{eprintln_colored, println},
+    variables, Context,
+};
 
-use crate::{terminal::println, CommandHistory, Context};
+pub(crate) mod aliases;
+mod completion;
+mod kill_ring;
+mod line_buffer;
+mod prompt;
+mod render;
+mod search;
+pub use self::aliases::{check, expand, Aliases};
+use self::completion::Completer;
+pub use self::completion::SESSION_VALUE_NAME;
+pub use self::kill_ring::KillRing;
+pub use self::prompt::PromptTemplate;
+use self::{
+    kill_ring::KillDirection, line_buffer::LineBuffer, prompt::PromptInfo, render::Renderer,
+    search::HistorySearch,
+};
 use anyhow::{bail, Result};
 use clap::Parser;
 use crossterm::{
     cursor,
     event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
     execute,
-    style::{self, Color},
+    style::{self, Color, Stylize},
     terminal::{self, ClearType},
 };
 use futures::StreamExt;
+use tokio::{select, sync::mpsc};
 
-pub const MAX_HISTORY_SIZE: usize = 100;
+/// Which prompt a line is being read for, each of which keeps its own history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptKind {
+    Rctf,
+    Termcraft,
+}
+
+/// What ended the reading of a line.
+enum Input {
+    Line(String),
+    /// A trigger needs rctf to act, interrupting this unfinished line.
+    Trigger(FiredTrigger, String),
+}
 
 impl<'a> Context<'a> {
     pub async fn get_next_command<P: Parser>(
-        &self,
-        prompt: &str,
-        history: &mut CommandHistory,
+        &mut self,
+        kind: PromptKind,
+        session_index: Option<usize>,
     ) -> Result<Option<P>> {
+        let completer = self.completer(P::command());
+        let session = session_index.map(|index| self.session_label(index));
+        let mut unfinished = String::new();
+
         loop {
-            let Some(next_line) = get_next_line(prompt, history).await? else {
-                return Ok(None);
+            let prompt = self.prompt(kind, session_index);
+            let history = self.history(kind).lines();
+            let labels: Vec<_> = (0..self.sessions.iter().len())
+                .map(|index| self.session_label(index))
+                .collect();
+            self.notifier.sync(&self.sessions, &labels, session_index);
+
+            let mut kill_ring = std::mem::take(&mut self.kill_ring);
+            let mut notifier = std::mem::take(&mut self.notifier);
+            let res = get_next_line(
+                &prompt,
+                &std::mem::take(&mut unfinished),
+                &history,
+                &completer,
+                &mut kill_ring,
+                &mut notifier,
+                &mut self.rx_fired,
+            )
+            .await;
+            self.kill_ring = kill_ring;
+            self.notifier = notifier;
+
+            let next_line = match res? {
+                None => return Ok(None),
+                Some(Input::Line(line)) => line,
+                Some(Input::Trigger(fired, line)) => {
+                    unfinished = line;
+                    self.run_trigger(fired).await?;
+                    continue;
+                }
             };
 
-            let next_line = self.parse_line(&next_line)?;
+            let settings = self.settings.history.clone();
+            self.history_mut(kind)
+                .push(&next_line, session.clone(), &settings);
 
             let args = match shlex::split(&next_line) {
                 None => {
@@ -43,6 +115,30 @@ impl<'a> Context<'a> {
                 Some(args) => args,
             };
 
+            let args = match self.expand_alias(args) {
+                Ok(args) => args,
+                Err(e) => {
+                    eprintln_colored(e, Color::Red)?;
+                    continue;
+                }
+            };
+
+            // Scripts in the config directory are commands too, unless they share a name with one.
+            let Some(name) = args.first() else {
+                continue;
+            };
+            if P::command().find_subcommand(name).is_none() {
+                if let Some(path) = user_script(name) {
+                    if let Err(e) = self
+                        .run_rhai(&path, args[1..].to_vec(), session_index)
+                        .await
+                    {
+                        eprintln_colored(e, Color::Red)?;
+                    }
+                    continue;
+                }
+            }
+
             let cmd = match P::try_parse_from(args) {
                 Ok(cmd) => cmd,
                 Err(e) if e.kind() == clap::error::ErrorKind::DisplayHelp => {
@@ -58,176 +154,395 @@ impl<'a> Context<'a> {
         }
     }
 
-    pub fn parse_line(&self, input: &str) -> Result<String> {
-        // TODO: implement
+    /// Render the configured prompt for `kind`.
+    fn prompt(&self, kind: PromptKind, session_index: Option<usize>) -> String {
+        let template = match kind {
+            PromptKind::Rctf => &self.settings.prompt.rctf,
+            PromptKind::Termcraft => &self.settings.prompt.termcraft,
+        };
+        let session = session_index.and_then(|index| self.sessions.get(index));
+        let sessions = self.sessions.iter().flatten().count();
+
+        template.render(&PromptInfo {
+            index: session_index,
+            name: session.and_then(|session| session.name()),
+            type_name: session.map(|session| session.type_name()),
+            host: session.map(|session| session.host()),
+            background: sessions - usize::from(session.is_some()),
+        })
+    }
 
-        // `\n`        new line
-        // `\r`        carriage return
-        // `\t`        horizontal tab
-        // variables
-        // double backslash
+    /// The session's name, or its index if it is unnamed.
+    pub(crate) fn session_label(&self, index: usize) -> String {
+        self.sessions
+            .get(index)
+            .and_then(|session| session.name())
+            .map(str::to_owned)
+            .unwrap_or_else(|| index.to_string())
+    }
 
-        Ok(input.to_owned())
+    fn completer(&self, command: clap::Command) -> Completer {
+        let variables = self.variables.keys().cloned().collect();
+        let sessions = self
+            .sessions
+            .iter()
+            .enumerate()
+            .filter_map(|(i, session)| session.as_ref().map(|session| (i, session)))
+            .flat_map(|(i, session)| {
+                std::iter::once(i.to_string()).chain(session.name().map(str::to_owned))
+            })
+            .collect();
+        Completer::new(command, variables, sessions)
+    }
+
+    pub fn parse_line(&self, input: &str) -> Result<Vec<u8>> {
+        variables::interpolate(input, &self.variables)
     }
 }
 
-fn print_prompt(prompt: &str) -> Result<()> {
-    let mut stdout = io::stdout();
+/// Shown on each extra line of multi-line input, lined up with the end of the prompt.
+fn continuation_prompt(prompt: &str) -> String {
+    let width = render::styled_width(prompt).saturating_sub(2);
+    format!("{:width$}{}", "", "> ".dim())
+}
+
+/// Whether the line can't be run yet because of an unterminated quote or a trailing backslash, in
+/// which case Enter starts a new line instead.
+pub(crate) fn is_incomplete(text: &str) -> bool {
+    shlex::split(text).is_none()
+}
+
+/// Terminals may send pasted newlines as carriage returns.
+fn normalize_newlines(text: &str) -> String {
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+fn draw_search(renderer: &mut Renderer, search: &HistorySearch, history: &[String]) -> Result<()> {
+    match search.found() {
+        Some((index, offset)) => renderer.draw(&search.prompt(), &history[index], offset),
+        None => renderer.draw(&search.prompt(), "", 0),
+    }
+}
 
-    execute!(
-        stdout,
-        style::SetForegroundColor(Color::Blue),
-        style::Print(prompt),
-        style::ResetColor,
-        style::Print("> "),
-    )?;
+/// Print completion candidates in columns below the prompt.
+fn print_candidates(candidates: &[String]) -> Result<()> {
+    let (width, _) = terminal::size()?;
+    let column_width = candidates
+        .iter()
+        .map(|candidate| render::display_width(candidate))
+        .max()
+        .unwrap_or(0)
+        + 2;
+    let columns = (width as usize / column_width).max(1);
+
+    let mut stdout = io::stdout();
+    for row in candidates.chunks(columns) {
+        for candidate in row {
+            let padding = column_width - render::display_width(candidate);
+            write!(stdout, "{candidate}{:padding$}", "")?;
+        }
+        write!(stdout, "\r\n")?;
+    }
 
     Ok(())
 }
 
-async fn get_next_line(prompt: &str, history: &mut CommandHistory) -> Result<Option<String>> {
-    let prompt_length = prompt.len() + 2;
+/// The previous editing action, used to merge consecutive kills and to rotate yanks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum LastAction {
+    Kill,
+    Yank(Range<usize>),
+    Other,
+}
 
+async fn get_next_line(
+    prompt: &str,
+    unfinished: &str,
+    history: &[String],
+    completer: &Completer,
+    kill_ring: &mut KillRing,
+    notifier: &mut Notifier,
+    rx_fired: &mut mpsc::UnboundedReceiver<FiredTrigger>,
+) -> Result<Option<Input>> {
     let mut stdout = io::stdout();
     let mut reader = EventStream::new();
 
-    let mut history_clone = history.clone();
-    let history_len = history.len();
-    let mut current_cmd = String::new();
-    let mut cmd = &mut current_cmd;
-    let mut column = 0usize;
+    // Edits to history entries are kept while browsing, but not saved to the history itself.
+    let mut entries: Vec<_> = history
+        .iter()
+        .cloned()
+        .chain([unfinished.to_owned()])
+        .map(LineBuffer::new)
+        .collect();
     let mut history_index = history.len();
+    let mut last_action = LastAction::Other;
+    let mut search: Option<HistorySearch> = None;
 
-    print_prompt(prompt)?;
+    let mut renderer = Renderer::new(continuation_prompt(prompt));
+    let line = &entries[history_index];
+    renderer.draw(prompt, line.text(), line.cursor())?;
 
     loop {
-        let Some(event) = reader.next().await else {
-            break;
-        };
-
-        if let Event::Key(KeyEvent {
-            code,
-            modifiers,
-            kind: KeyEventKind::Press | KeyEventKind::Repeat,
-            ..
-        }) = event?
-        {
-            match (code, modifiers) {
-                (KeyCode::Esc, _) => {
-                    write!(stdout, "\r\n")?;
-                    return Ok(None);
-                }
-                (KeyCode::Char('c'), KeyModifiers::CONTROL) => {
-                    write!(stdout, "^C\r\n")?;
-                    return Ok(None);
-                }
-                (KeyCode::Enter, _) => {
-                    write!(stdout, "\r\n")?;
-                    break;
-                }
-                (KeyCode::Backspace, _) => {
-                    if column == 0 {
-                        continue;
-                    }
-
-                    column -= 1;
-                    cmd.remove(column);
+        let event = select! {
+            event = reader.next() => match event {
+                Some(event) => event?,
+                None => break,
+            },
+            notices = notifier.next() => {
+                renderer.clear()?;
+                for notice in notices {
                     execute!(
                         stdout,
-                        cursor::MoveLeft(1),
-                        terminal::Clear(ClearType::UntilNewLine),
-                        cursor::SavePosition,
-                        style::Print(&cmd[column..]),
-                        cursor::RestorePosition,
+                        style::PrintStyledContent(notice.dark_yellow()),
+                        style::Print("\r\n")
                     )?;
                 }
-                (KeyCode::Delete, _) => {
-                    if column == cmd.len() {
-                        continue;
+                match &search {
+                    Some(active) => draw_search(&mut renderer, active, history)?,
+                    None => {
+                        let line = &entries[history_index];
+                        renderer.draw(prompt, line.text(), line.cursor())?;
                     }
-
-                    cmd.remove(column);
-                    execute!(
-                        stdout,
-                        cursor::SavePosition,
-                        terminal::Clear(ClearType::UntilNewLine),
-                        style::Print(&cmd[column..]),
-                        cursor::RestorePosition,
-                    )?;
                 }
-                (KeyCode::Up, _) => {
-                    if history_index == 0 {
-                        continue;
-                    }
+                continue;
+            }
+            Some(fired) = rx_fired.recv() => {
+                renderer.clear()?;
+                let line = entries.swap_remove(history_index).text().to_owned();
+                return Ok(Some(Input::Trigger(fired, line)));
+            }
+        };
 
-                    if history_index == history_len {
-                        current_cmd = cmd.clone();
-                    }
-                    history_index -= 1;
-                    cmd = history_clone.get_mut(history_index).unwrap();
-                    column = cmd.len();
-                    execute!(
-                        stdout,
-                        cursor::MoveToColumn(prompt_length as u16),
-                        terminal::Clear(ClearType::UntilNewLine),
-                        style::Print(&cmd),
-                    )?;
+        let (code, modifiers) = match event {
+            Event::Key(KeyEvent {
+                code,
+                modifiers,
+                kind: KeyEventKind::Press | KeyEventKind::Repeat,
+                ..
+            }) => (code, modifiers),
+            Event::Paste(text) if search.is_none() => {
+                let line = &mut entries[history_index];
+                line.insert(&normalize_newlines(&text));
+                last_action = LastAction::Other;
+                renderer.draw(prompt, line.text(), line.cursor())?;
+                continue;
+            }
+            _ => continue,
+        };
+
+        if let Some(active) = search.as_mut() {
+            match (code, modifiers) {
+                (KeyCode::Char('r'), KeyModifiers::CONTROL) => active.next(history),
+                (KeyCode::Char(c), KeyModifiers::NONE | KeyModifiers::SHIFT) => {
+                    active.push(c, history)
                 }
-                (KeyCode::Down, _) => {
-                    if history_index == history_len {
-                        continue;
+                (KeyCode::Backspace, _) => active.pop(history),
+                (KeyCode::Esc, _) | (KeyCode::Char('g' | 'c'), KeyModifiers::CONTROL) => {
+                    search = None;
+                    let line = &entries[history_index];
+                    renderer.draw(prompt, line.text(), line.cursor())?;
+                    continue;
+                }
+                (code, _) => {
+                    // Accept the match, then either run it or leave it for editing.
+                    if let Some((index, offset)) = active.found() {
+                        history_index = index;
+                        entries[index] = LineBuffer::new(history[index].clone());
+                        entries[index].move_to(offset);
                     }
+                    search = None;
+                    let line = &entries[history_index];
+                    renderer.draw(prompt, line.text(), line.cursor())?;
 
-                    history_index += 1;
-                    if history_index == history.len() {
-                        cmd = &mut current_cmd;
-                    } else {
-                        cmd = history_clone.get_mut(history_index).unwrap();
+                    if code == KeyCode::Enter {
+                        renderer.finish("")?;
+                        break;
                     }
-                    column = cmd.len();
-                    execute!(
-                        stdout,
-                        cursor::MoveToColumn(prompt_length as u16),
-                        terminal::Clear(ClearType::UntilNewLine),
-                        style::Print(&cmd),
-                    )?;
+                    continue;
                 }
-                (KeyCode::Left, _) => {
-                    if column == 0 {
-                        continue;
-                    }
+            }
 
-                    column -= 1;
-                    execute!(stdout, cursor::MoveLeft(1),)?;
-                }
-                (KeyCode::Right, _) => {
-                    if column == cmd.len() {
-                        continue;
+            draw_search(&mut renderer, search.as_ref().unwrap(), history)?;
+            continue;
+        }
+
+        let line = &mut entries[history_index];
+        let mut action = LastAction::Other;
+
+        match (code, modifiers) {
+            (KeyCode::Esc, _) => {
+                renderer.finish("")?;
+                return Ok(None);
+            }
+            (KeyCode::Char('c'), KeyModifiers::CONTROL) => {
+                renderer.finish("^C")?;
+                return Ok(None);
+            }
+            (KeyCode::Char('d'), KeyModifiers::CONTROL) if line.is_empty() => {
+                renderer.finish("")?;
+                return Ok(None);
+            }
+            (KeyCode::Enter, _) if is_incomplete(line.text()) => {
+                line.move_end();
+                line.insert("\n");
+            }
+            (KeyCode::Enter, _) => {
+                renderer.finish("")?;
+                break;
+            }
+            (KeyCode::Tab, _) => {
+                let completion = completer.complete(line.text(), line.cursor());
+                let word = &line.text()[completion.start..line.cursor()];
+
+                let replacement = match completion.candidates.as_slice() {
+                    [] => continue,
+                    [candidate] if completion.terminal => format!("{candidate} "),
+                    [candidate] => candidate.clone(),
+                    candidates => {
+                        let prefix = completion.common_prefix();
+                        if prefix.len() > word.len() {
+                            prefix.to_owned()
+                        } else {
+                            renderer.finish("")?;
+                            print_candidates(candidates)?;
+                            renderer.draw(prompt, line.text(), line.cursor())?;
+                            continue;
+                        }
                     }
+                };
 
-                    column += 1;
-                    execute!(stdout, cursor::MoveRight(1),)?;
-                }
-                (KeyCode::Char(c), KeyModifiers::NONE | KeyModifiers::SHIFT) => {
-                    cmd.insert(column, c);
-                    execute!(
-                        stdout,
-                        style::Print(c),
-                        cursor::SavePosition,
-                        terminal::Clear(ClearType::UntilNewLine),
-                        style::Print(&cmd[column + 1..]),
-                        cursor::RestorePosition,
-                    )?;
-                    column += 1;
-                }
-                _ => {}
+                line.replace(completion.start..line.cursor(), &replacement);
+            }
+            (KeyCode::Char('r'), KeyModifiers::CONTROL) => {
+                let active = HistorySearch::default();
+                draw_search(&mut renderer, &active, history)?;
+                search = Some(active);
+                continue;
+            }
+            (KeyCode::Up, _) | (KeyCode::Char('p'), KeyModifiers::CONTROL) => {
+                history_index = history_index.saturating_sub(1);
+            }
+            (KeyCode::Down, _) | (KeyCode::Char('n'), KeyModifiers::CONTROL) => {
+                history_index = (history_index + 1).min(history.len());
+            }
+            (KeyCode::Left, KeyModifiers::NONE) | (KeyCode::Char('b'), KeyModifiers::CONTROL) => {
+                line.move_left()
+            }
+            (KeyCode::Right, KeyModifiers::NONE) | (KeyCode::Char('f'), KeyModifiers::CONTROL) => {
+                line.move_right()
+            }
+            (KeyCode::Left, KeyModifiers::CONTROL) | (KeyCode::Char('b'), KeyModifiers::ALT) => {
+                line.move_word_left()
+            }
+            (KeyCode::Right, KeyModifiers::CONTROL) | (KeyCode::Char('f'), KeyModifiers::ALT) => {
+                line.move_word_right()
+            }
+            (KeyCode::Home, _) | (KeyCode::Char('a'), KeyModifiers::CONTROL) => line.move_home(),
+            (KeyCode::End, _) | (KeyCode::Char('e'), KeyModifiers::CONTROL) => line.move_end(),
+            (KeyCode::Backspace, KeyModifiers::ALT | KeyModifiers::CONTROL) => {
+                let killed = line.kill_word_back();
+                kill_ring.kill(
+                    killed,
+                    KillDirection::Backward,
+                    last_action == LastAction::Kill,
+                );
+                action = LastAction::Kill;
+            }
+            (KeyCode::Backspace, _) => {
+                line.delete_back();
+            }
+            (KeyCode::Delete, _) | (KeyCode::Char('d'), KeyModifiers::CONTROL) => {
+                line.delete_forward();
             }
+            (KeyCode::Char('w'), KeyModifiers::CONTROL) => {
+                let killed = line.kill_big_word_back();
+                kill_ring.kill(
+                    killed,
+                    KillDirection::Backward,
+                    last_action == LastAction::Kill,
+                );
+                action = LastAction::Kill;
+            }
+            (KeyCode::Char('d'), KeyModifiers::ALT) => {
+                let killed = line.kill_word_forward();
+                kill_ring.kill(
+                    killed,
+                    KillDirection::Forward,
+                    last_action == LastAction::Kill,
+                );
+                action = LastAction::Kill;
+            }
+            (KeyCode::Char('u'), KeyModifiers::CONTROL) => {
+                let killed = line.kill_to_start();
+                kill_ring.kill(
+                    killed,
+                    KillDirection::Backward,
+                    last_action == LastAction::Kill,
+                );
+                action = LastAction::Kill;
+            }
+            (KeyCode::Char('k'), KeyModifiers::CONTROL) => {
+                let killed = line.kill_to_end();
+                kill_ring.kill(
+                    killed,
+                    KillDirection::Forward,
+                    last_action == LastAction::Kill,
+                );
+                action = LastAction::Kill;
+            }
+            (KeyCode::Char('y'), KeyModifiers::CONTROL) => {
+                let Some(text) = kill_ring.yank() else {
+                    continue;
+                };
+                let start = line.cursor();
+                line.insert(text);
+                action = LastAction::Yank(start..line.cursor());
+            }
+            (KeyCode::Char('y'), KeyModifiers::ALT) => {
+                let LastAction::Yank(range) = last_action.clone() else {
+                    continue;
+                };
+                let Some(text) = kill_ring.yank_pop() else {
+                    continue;
+                };
+                line.replace(range.clone(), text);
+                action = LastAction::Yank(range.start..line.cursor());
+            }
+            (KeyCode::Char('l'), KeyModifiers::CONTROL) => {
+                execute!(
+                    stdout,
+                    terminal::Clear(ClearType::All),
+                    cursor::MoveTo(0, 0)
+                )?;
+                renderer.reset();
+            }
+            (KeyCode::Char(c), KeyModifiers::NONE | KeyModifiers::SHIFT) => {
+                line.insert(c.encode_utf8(&mut [0; 4]));
+            }
+            _ => continue,
         }
+
+        last_action = action;
+        let line = &entries[history_index];
+        renderer.draw(prompt, line.text(), line.cursor())?;
     }
 
-    history.push_back(cmd.clone());
-    while history.len() > MAX_HISTORY_SIZE {
-        history.pop_front();
+    Ok(Some(Input::Line(
+        entries.swap_remove(history_index).text().to_owned(),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn incomplete_lines() {
+        assert!(!is_incomplete("printf 'id\n'"));
+        assert!(is_incomplete("printf 'id"));
+        assert!(is_incomplete("printf \"a\nb"));
+        assert!(is_incomplete("ssh root \\"));
+        assert!(!is_incomplete("printf \\\\"));
+        assert_eq!(normalize_newlines("a\r\nb\rc\n"), "a\nb\nc\n");
     }
-    Ok(Some(cmd.to_string()))
 }
@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+
+/// Commands defined with `alias`, by name.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Aliases(BTreeMap<String, String>);
+
+impl Aliases {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+
+    pub fn set(&mut self, name: String, template: String) {
+        self.0.insert(name, template);
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        self.0.remove(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.0.iter()
+    }
+}
+
+/// Check that an alias's command is something that can be run.
+pub fn check(template: &str) -> Result<()> {
+    match shlex::split(template) {
+        None => bail!("Invalid quoting."),
+        Some(words) if words.iter().all(String::is_empty) => bail!("An alias can't be empty."),
+        Some(_) => Ok(()),
+    }
+}
+
+/// Expand an alias's command with the arguments it was given: `$1`, `$2`, ... are replaced with
+/// single arguments and `$@` with all of them. Without any of these, the arguments are appended.
+pub fn expand(template: &str, args: &[String]) -> Result<Vec<String>> {
+    let Some(words) = shlex::split(template) else {
+        bail!("Invalid quoting.");
+    };
+    let placeholder = Regex::new(r"\$(@|[1-9][0-9]*)").unwrap();
+
+    let mut used = false;
+    let mut missing = None;
+    let mut expanded = Vec::new();
+    for word in words {
+        if word == "$@" {
+            used = true;
+            expanded.extend_from_slice(args);
+            continue;
+        }
+        let word = placeholder.replace_all(&word, |captures: &Captures| {
+            used = true;
+            if &captures[1] == "@" {
+                return args.join(" ");
+            }
+            let n = captures[1].parse().unwrap_or(usize::MAX);
+            args.get(n - 1).cloned().unwrap_or_else(|| {
+                missing = missing.max(Some(n));
+                String::new()
+            })
+        });
+        expanded.push(word.into_owned());
+    }
+
+    if let Some(n) = missing {
+        bail!("Expected at least {n} arguments.");
+    }
+    if !used {
+        expanded.extend_from_slice(args);
+    }
+    if expanded.iter().all(String::is_empty) {
+        bail!("The alias expanded to nothing.");
+    }
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn expansion() {
+        assert_eq!(
+            expand("session send @web", &args(&["id"])).unwrap(),
+            args(&["session", "send", "@web", "id"])
+        );
+        assert_eq!(
+            expand("session send $2 '$1 -la'", &args(&["ls", "0"])).unwrap(),
+            args(&["session", "send", "0", "ls -la"])
+        );
+        assert_eq!(
+            expand("broadcast -c $@", &args(&["web", "cat /flag"])).unwrap(),
+            args(&["broadcast", "-c", "web", "cat /flag"])
+        );
+        assert_eq!(
+            expand("printf '$@\\n'", &args(&["a", "b"])).unwrap(),
+            args(&["printf", "a b\\n"])
+        );
+        assert_eq!(
+            expand("expect '\\$ $'", &[]).unwrap(),
+            args(&["expect", "\\$ $"])
+        );
+        assert!(expand("session send $1 $2", &args(&["0"])).is_err());
+        assert!(expand("$@", &[]).is_err());
+        assert!(check("session send $1").is_ok());
+        assert!(check("''").is_err());
+        assert!(check("").is_err());
+    }
+}
@@ -0,0 +1,332 @@
+use std::{fs, path::Path};
+
+use clap::{Arg, ArgAction, Command, ValueHint};
+
+/// Arguments with this value name are completed with session names and indices.
+pub const SESSION_VALUE_NAME: &str = "SESSION";
+
+/// Context-aware completion driven by the clap definition of the current prompt's commands.
+pub struct Completer {
+    command: Command,
+    variables: Vec<String>,
+    sessions: Vec<String>,
+}
+
+/// The result of completing the word before the cursor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completion {
+    /// Byte index in the line where the completed word starts.
+    pub start: usize,
+    pub candidates: Vec<String>,
+    /// Whether a single candidate completes an entire argument, so a space should follow it.
+    pub terminal: bool,
+}
+
+impl Completer {
+    pub fn new(mut command: Command, variables: Vec<String>, sessions: Vec<String>) -> Self {
+        command.build();
+        Self {
+            command,
+            variables,
+            sessions,
+        }
+    }
+
+    /// Complete the word ending at byte index `cursor` in `line`.
+    pub fn complete(&self, line: &str, cursor: usize) -> Completion {
+        let line = &line[..cursor];
+        let start = line
+            .char_indices()
+            .rev()
+            .find(|(_, c)| c.is_whitespace())
+            .map(|(i, c)| i + c.len_utf8())
+            .unwrap_or(0);
+        let word = &line[start..];
+
+        if let Some(hash) = word.rfind('#') {
+            let prefix = word[hash + 1..].trim_start_matches('{');
+            let start = start + word.len() - prefix.len();
+            return Completion {
+                start,
+                candidates: filter(self.variables.iter().cloned(), prefix),
+                terminal: false,
+            };
+        }
+
+        let previous = shlex::split(&line[..start]).unwrap_or_else(|| {
+            line[..start]
+                .split_whitespace()
+                .map(str::to_owned)
+                .collect()
+        });
+
+        let mut command = &self.command;
+        let mut positionals = 0usize;
+        let mut pending_option: Option<&Arg> = None;
+        for token in &previous {
+            if pending_option.take().is_some() {
+                continue;
+            }
+
+            if let Some(long) = token.strip_prefix("--") {
+                let (long, value) = long.split_once('=').unwrap_or((long, ""));
+                let arg = command
+                    .get_arguments()
+                    .find(|arg| arg.get_long() == Some(long));
+                if arg.is_some_and(takes_value) && value.is_empty() && !token.contains('=') {
+                    pending_option = arg;
+                }
+            } else if let Some(short) = token.strip_prefix('-').filter(|short| !short.is_empty()) {
+                let last = short.chars().last();
+                let arg = command
+                    .get_arguments()
+                    .find(|arg| arg.get_short().is_some() && arg.get_short() == last);
+                if arg.is_some_and(takes_value) {
+                    pending_option = arg;
+                }
+            } else if let Some(subcommand) = (positionals == 0)
+                .then(|| command.find_subcommand(token))
+                .flatten()
+            {
+                command = subcommand;
+            } else {
+                positionals += 1;
+            }
+        }
+
+        if let Some(arg) = pending_option {
+            return self.complete_value(arg, start, word);
+        }
+
+        if word.starts_with('-') {
+            let flags = command
+                .get_arguments()
+                .filter(|arg| !arg.is_hide_set())
+                .flat_map(|arg| {
+                    let long = arg.get_long().map(|long| format!("--{long}"));
+                    let short = arg.get_short().map(|short| format!("-{short}"));
+                    long.into_iter().chain(short)
+                });
+            return Completion {
+                start,
+                candidates: filter(flags, word),
+                terminal: true,
+            };
+        }
+
+        if command.has_subcommands() && positionals == 0 {
+            let subcommands = command
+                .get_subcommands()
+                .filter(|subcommand| !subcommand.is_hide_set())
+                .map(|subcommand| subcommand.get_name().to_owned());
+            return Completion {
+                start,
+                candidates: filter(subcommands, word),
+                terminal: true,
+            };
+        }
+
+        match command.get_positionals().nth(positionals) {
+            Some(arg) => self.complete_value(arg, start, word),
+            None => Completion {
+                start,
+                candidates: Vec::new(),
+                terminal: true,
+            },
+        }
+    }
+
+    fn complete_value(&self, arg: &Arg, start: usize, word: &str) -> Completion {
+        let is_session = arg
+            .get_value_names()
+            .is_some_and(|names| names.iter().any(|name| name == SESSION_VALUE_NAME));
+
+        if is_session {
+            Completion {
+                start,
+                candidates: filter(self.sessions.iter().cloned(), word),
+                terminal: true,
+            }
+        } else if matches!(
+            arg.get_value_hint(),
+            ValueHint::AnyPath | ValueHint::FilePath | ValueHint::DirPath
+        ) {
+            complete_path(start, word)
+        } else {
+            let values = arg
+                .get_possible_values()
+                .into_iter()
+                .filter(|value| !value.is_hide_set())
+                .map(|value| value.get_name().to_owned());
+            Completion {
+                start,
+                candidates: filter(values, word),
+                terminal: true,
+            }
+        }
+    }
+}
+
+impl Completion {
+    /// The longest prefix shared by every candidate.
+    pub fn common_prefix(&self) -> &str {
+        let Some(first) = self.candidates.first() else {
+            return "";
+        };
+
+        let len = self.candidates[1..]
+            .iter()
+            .fold(first.len(), |len, candidate| {
+                first[..len]
+                    .char_indices()
+                    .zip(candidate.chars())
+                    .find(|((_, a), b)| a != b)
+                    .map(|((i, _), _)| i)
+                    .unwrap_or(len.min(candidate.len()))
+            });
+        &first[..len]
+    }
+}
+
+fn takes_value(arg: &Arg) -> bool {
+    matches!(arg.get_action(), ArgAction::Set | ArgAction::Append)
+}
+
+fn filter(candidates: impl Iterator<Item = String>, prefix: &str) -> Vec<String> {
+    let mut candidates: Vec<_> = candidates
+        .filter(|candidate| candidate.starts_with(prefix))
+        .collect();
+    candidates.sort();
+    candidates.dedup();
+    candidates
+}
+
+fn complete_path(start: usize, word: &str) -> Completion {
+    let unescaped = word.replace("\\ ", " ");
+    let (dir, prefix) = match unescaped.rfind('/') {
+        Some(i) => (&unescaped[..=i], &unescaped[i + 1..]),
+        None => ("", unescaped.as_str()),
+    };
+
+    let read_dir = fs::read_dir(if dir.is_empty() {
+        Path::new(".")
+    } else {
+        Path::new(dir)
+    });
+    let mut candidates: Vec<_> = read_dir
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let suffix = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{dir}{name}{suffix}").replace(' ', "\\ "))
+        })
+        .collect();
+    candidates.sort();
+
+    let terminal = !(candidates.len() == 1 && candidates[0].ends_with('/'));
+    Completion {
+        start,
+        candidates,
+        terminal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{Parser, Subcommand};
+
+    use super::*;
+
+    #[derive(Debug, Parser)]
+    #[command(multicall = true)]
+    struct Cli {
+        #[command(subcommand)]
+        command: Commands,
+    }
+
+    #[derive(Debug, Subcommand)]
+    enum Commands {
+        Session {
+            #[arg(value_name = SESSION_VALUE_NAME)]
+            name: Option<String>,
+        },
+        Ssh {
+            username: String,
+            #[arg(short, long)]
+            port: Option<u16>,
+            #[arg(long)]
+            verbose: bool,
+        },
+        Source {
+            #[arg(value_hint = ValueHint::FilePath)]
+            file: String,
+        },
+        Var {
+            #[command(subcommand)]
+            command: VarCommands,
+        },
+    }
+
+    #[derive(Debug, Subcommand)]
+    enum VarCommands {
+        Get { name: String },
+        Set { name: String },
+    }
+
+    fn complete(line: &str) -> Completion {
+        let completer = Completer::new(
+            <Cli as clap::CommandFactory>::command(),
+            vec!["base".to_string(), "buf".to_string(), "addr".to_string()],
+            vec!["0".to_string(), "web".to_string()],
+        );
+        completer.complete(line, line.len())
+    }
+
+    #[test]
+    fn subcommands() {
+        assert_eq!(complete("s").candidates, ["session", "source", "ssh"]);
+        assert_eq!(complete("var s").candidates, ["set"]);
+        assert_eq!(complete("var set ").candidates, Vec::<String>::new());
+    }
+
+    #[test]
+    fn flags() {
+        assert_eq!(
+            complete("ssh root -").candidates,
+            ["--help", "--port", "--verbose", "-h", "-p"]
+        );
+        assert_eq!(complete("ssh root --v").candidates, ["--verbose"]);
+        assert_eq!(complete("ssh --port 22 root --p").candidates, ["--port"]);
+    }
+
+    #[test]
+    fn variables() {
+        let completion = complete("printf \"#{b");
+        assert_eq!(completion.candidates, ["base", "buf"]);
+        assert_eq!(completion.start, 10);
+        assert_eq!(completion.common_prefix(), "b");
+        assert_eq!(complete("printf #ad").candidates, ["addr"]);
+    }
+
+    #[test]
+    fn sessions() {
+        assert_eq!(complete("session ").candidates, ["0", "web"]);
+        assert_eq!(complete("session w").candidates, ["web"]);
+        // Spaces that aren't ASCII are more than one byte long.
+        let completion = complete("session\u{3000}w");
+        assert_eq!(completion.start, "session\u{3000}".len());
+        assert_eq!(complete("session\u{a0}").start, "session\u{a0}".len());
+    }
+
+    #[test]
+    fn paths() {
+        let completion = complete("source src/");
+        assert!(completion.candidates.contains(&"src/lib.rs".to_string()));
+        assert!(completion.candidates.contains(&"src/input/".to_string()));
+    }
+}
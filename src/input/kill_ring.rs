@@ -0,0 +1,51 @@
+use std::collections::VecDeque;
+
+pub const MAX_KILL_RING_SIZE: usize = 16;
+
+/// Text removed with the kill commands (Ctrl-U, Ctrl-K, Ctrl-W, ...), most recent first.
+#[derive(Debug, Clone, Default)]
+pub struct KillRing {
+    entries: VecDeque<String>,
+    yank_index: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KillDirection {
+    Backward,
+    Forward,
+}
+
+impl KillRing {
+    /// Add killed text to the ring, or merge it with the last kill if kills are consecutive.
+    pub fn kill(&mut self, text: String, direction: KillDirection, append: bool) {
+        if text.is_empty() {
+            return;
+        }
+
+        match (append, self.entries.front_mut()) {
+            (true, Some(last)) => match direction {
+                KillDirection::Backward => last.insert_str(0, &text),
+                KillDirection::Forward => last.push_str(&text),
+            },
+            _ => {
+                self.entries.push_front(text);
+                self.entries.truncate(MAX_KILL_RING_SIZE);
+            }
+        }
+    }
+
+    /// The most recently killed text.
+    pub fn yank(&mut self) -> Option<&str> {
+        self.yank_index = 0;
+        self.entries.front().map(String::as_str)
+    }
+
+    /// Rotate to the next older kill, to replace a previous yank.
+    pub fn yank_pop(&mut self) -> Option<&str> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        self.yank_index = (self.yank_index + 1) % self.entries.len();
+        self.entries.get(self.yank_index).map(String::as_str)
+    }
+}
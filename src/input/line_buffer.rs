@@ -0,0 +1,232 @@
+use std::ops::Range;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+/// The text being edited at a prompt, along with the cursor's byte index into it.
+///
+/// The cursor always sits on a grapheme cluster boundary, so that combined characters and emoji
+/// are moved over and deleted as a whole.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LineBuffer {
+    text: String,
+    cursor: usize,
+}
+
+impl LineBuffer {
+    /// Create a buffer with the cursor at the end of `text`.
+    pub fn new(text: String) -> Self {
+        let cursor = text.len();
+        Self { text, cursor }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    pub fn insert(&mut self, text: &str) {
+        self.text.insert_str(self.cursor, text);
+        self.cursor += text.len();
+    }
+
+    /// Replace `range` with `text`, leaving the cursor after the inserted text.
+    pub fn replace(&mut self, range: Range<usize>, text: &str) {
+        self.text.replace_range(range.clone(), text);
+        self.cursor = range.start + text.len();
+    }
+
+    /// Remove and return the text in `range`, leaving the cursor where it was.
+    pub fn drain(&mut self, range: Range<usize>) -> String {
+        let removed: String = self.text.drain(range.clone()).collect();
+        if self.cursor >= range.end {
+            self.cursor -= range.len();
+        } else if self.cursor > range.start {
+            self.cursor = range.start;
+        }
+        removed
+    }
+
+    pub fn move_to(&mut self, position: usize) {
+        self.cursor = position.min(self.text.len());
+    }
+
+    pub fn move_home(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn move_end(&mut self) {
+        self.cursor = self.text.len();
+    }
+
+    pub fn move_left(&mut self) {
+        self.cursor = self.previous_boundary();
+    }
+
+    pub fn move_right(&mut self) {
+        self.cursor = self.next_boundary();
+    }
+
+    pub fn move_word_left(&mut self) {
+        self.cursor = self.word_start();
+    }
+
+    pub fn move_word_right(&mut self) {
+        self.cursor = self.word_end();
+    }
+
+    pub fn delete_back(&mut self) -> bool {
+        let start = self.previous_boundary();
+        !self.drain(start..self.cursor).is_empty()
+    }
+
+    pub fn delete_forward(&mut self) -> bool {
+        let end = self.next_boundary();
+        !self.drain(self.cursor..end).is_empty()
+    }
+
+    pub fn kill_to_start(&mut self) -> String {
+        self.drain(0..self.cursor)
+    }
+
+    pub fn kill_to_end(&mut self) -> String {
+        self.drain(self.cursor..self.text.len())
+    }
+
+    /// Kill back to the previous whitespace, like Ctrl-W in a shell.
+    pub fn kill_big_word_back(&mut self) -> String {
+        let before = self.text[..self.cursor].trim_end_matches(char::is_whitespace);
+        let start = before
+            .rfind(char::is_whitespace)
+            .map(|i| i + before[i..].chars().next().unwrap().len_utf8())
+            .unwrap_or(0);
+        self.drain(start..self.cursor)
+    }
+
+    pub fn kill_word_back(&mut self) -> String {
+        self.drain(self.word_start()..self.cursor)
+    }
+
+    pub fn kill_word_forward(&mut self) -> String {
+        self.drain(self.cursor..self.word_end())
+    }
+
+    fn previous_boundary(&self) -> usize {
+        self.text[..self.cursor]
+            .grapheme_indices(true)
+            .next_back()
+            .map(|(i, _)| i)
+            .unwrap_or(0)
+    }
+
+    fn next_boundary(&self) -> usize {
+        self.text[self.cursor..]
+            .graphemes(true)
+            .next()
+            .map(|grapheme| self.cursor + grapheme.len())
+            .unwrap_or(self.cursor)
+    }
+
+    /// Start of the alphanumeric word before the cursor.
+    fn word_start(&self) -> usize {
+        let before = self.text[..self.cursor].trim_end_matches(|c: char| !c.is_alphanumeric());
+        before
+            .char_indices()
+            .rev()
+            .take_while(|(_, c)| c.is_alphanumeric())
+            .last()
+            .map(|(i, _)| i)
+            .unwrap_or(before.len())
+    }
+
+    /// End of the alphanumeric word after the cursor.
+    fn word_end(&self) -> usize {
+        let after = &self.text[self.cursor..];
+        let skipped = after.len()
+            - after
+                .trim_start_matches(|c: char| !c.is_alphanumeric())
+                .len();
+        let word = after[skipped..]
+            .find(|c: char| !c.is_alphanumeric())
+            .unwrap_or(after.len() - skipped);
+        self.cursor + skipped + word
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer_at(text: &str, cursor: usize) -> LineBuffer {
+        LineBuffer {
+            text: text.to_string(),
+            cursor,
+        }
+    }
+
+    #[test]
+    fn word_motions() {
+        let mut buffer = buffer_at("ssh root@10.0.0.1 --port 22", 27);
+
+        buffer.move_word_left();
+        assert_eq!(buffer.cursor(), 25);
+        buffer.move_word_left();
+        assert_eq!(buffer.cursor(), 20);
+        buffer.move_word_left();
+        assert_eq!(buffer.cursor(), 16);
+
+        buffer.move_word_right();
+        assert_eq!(buffer.cursor(), 17);
+        buffer.move_word_right();
+        assert_eq!(buffer.cursor(), 24);
+    }
+
+    #[test]
+    fn kills() {
+        let mut buffer = buffer_at("printf hello world", 12);
+        assert_eq!(buffer.kill_big_word_back(), "hello");
+        assert_eq!(buffer.text(), "printf  world");
+        assert_eq!(buffer.kill_word_forward(), " world");
+        assert_eq!(buffer.kill_to_start(), "printf ");
+        assert!(buffer.is_empty());
+
+        let mut other = buffer_at("var set name value", 8);
+        assert_eq!(other.kill_to_end(), "name value");
+        assert_eq!(other.kill_word_back(), "set ");
+        assert_eq!(other.text(), "var ");
+    }
+
+    #[test]
+    fn multibyte() {
+        let mut buffer = buffer_at("héllo", 3);
+        buffer.move_left();
+        assert_eq!(buffer.cursor(), 1);
+        assert!(buffer.delete_forward());
+        assert_eq!(buffer.text(), "hllo");
+        buffer.insert("é");
+        assert_eq!(buffer.cursor(), 3);
+        assert!(buffer.delete_back());
+        assert_eq!(buffer.text(), "hllo");
+    }
+
+    #[test]
+    fn graphemes() {
+        // `e` followed by a combining acute accent, and a family emoji joined with ZWJs
+        let mut buffer = LineBuffer::new("e\u{301}👨‍👩‍👧".to_string());
+        buffer.move_left();
+        assert_eq!(buffer.cursor(), 3);
+        buffer.move_left();
+        assert_eq!(buffer.cursor(), 0);
+        buffer.move_right();
+        assert!(buffer.delete_forward());
+        assert_eq!(buffer.text(), "e\u{301}");
+        assert!(buffer.delete_back());
+        assert!(buffer.is_empty());
+    }
+}
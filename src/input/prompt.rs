@@ -0,0 +1,247 @@
+use std::{fmt::Write, str::FromStr};
+
+use anyhow::{bail, Error, Result};
+use chrono::Local;
+use crossterm::{
+    style::{Attribute, Color, SetAttribute, SetForegroundColor},
+    Command,
+};
+use serde::Deserialize;
+
+/// A prompt made of text, `{placeholder}`s and `{colour}`s, e.g.
+/// `{blue}termcraft{reset} {session}> `. Literal braces are written `{{` and `}}`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct PromptTemplate(Vec<Segment>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Text(String),
+    Field(Field),
+    Style(Style),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    /// The session's name, or its index if it is unnamed.
+    Session,
+    Index,
+    Name,
+    Type,
+    Host,
+    /// Number of sessions other than the current one.
+    Background,
+    /// Name of the current directory.
+    Workspace,
+    Time,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Style {
+    Color(Color),
+    Bold,
+    Dim,
+    Reset,
+}
+
+/// What a prompt can show about the current state.
+#[derive(Debug, Clone, Default)]
+pub struct PromptInfo<'a> {
+    pub index: Option<usize>,
+    pub name: Option<&'a str>,
+    pub type_name: Option<&'a str>,
+    pub host: Option<&'a str>,
+    pub background: usize,
+}
+
+impl PromptTemplate {
+    pub fn render(&self, info: &PromptInfo) -> String {
+        let mut prompt = String::new();
+        let mut styled = false;
+
+        for segment in &self.0 {
+            match segment {
+                Segment::Text(text) => prompt.push_str(text),
+                Segment::Field(field) => prompt.push_str(&field.value(info)),
+                Segment::Style(style) => {
+                    styled = true;
+                    // Writing to a `String` can't fail.
+                    let _ = match style {
+                        Style::Color(color) => SetForegroundColor(*color).write_ansi(&mut prompt),
+                        Style::Bold => SetAttribute(Attribute::Bold).write_ansi(&mut prompt),
+                        Style::Dim => SetAttribute(Attribute::Dim).write_ansi(&mut prompt),
+                        Style::Reset => SetAttribute(Attribute::Reset).write_ansi(&mut prompt),
+                    };
+                }
+            }
+        }
+
+        // Don't let the prompt's colours leak into the line being typed.
+        if styled {
+            let _ = write!(prompt, "{}", Attribute::Reset);
+        }
+        prompt
+    }
+}
+
+impl Field {
+    fn value(self, info: &PromptInfo) -> String {
+        match self {
+            Field::Session => match (info.name, info.index) {
+                (Some(name), _) => name.to_owned(),
+                (None, Some(index)) => index.to_string(),
+                (None, None) => String::new(),
+            },
+            Field::Index => info
+                .index
+                .map(|index| index.to_string())
+                .unwrap_or_default(),
+            Field::Name => info.name.unwrap_or_default().to_owned(),
+            Field::Type => info.type_name.unwrap_or_default().to_owned(),
+            Field::Host => info.host.unwrap_or_default().to_owned(),
+            Field::Background => info.background.to_string(),
+            Field::Workspace => std::env::current_dir()
+                .ok()
+                .and_then(|dir| Some(dir.file_name()?.to_string_lossy().into_owned()))
+                .unwrap_or_default(),
+            Field::Time => Local::now().format("%H:%M:%S").to_string(),
+        }
+    }
+}
+
+impl FromStr for Segment {
+    type Err = Error;
+
+    /// Parse the contents of a `{...}`.
+    fn from_str(name: &str) -> Result<Self> {
+        let field = match name {
+            "session" => Field::Session,
+            "index" => Field::Index,
+            "name" => Field::Name,
+            "type" => Field::Type,
+            "host" => Field::Host,
+            "background" => Field::Background,
+            "workspace" => Field::Workspace,
+            "time" => Field::Time,
+            _ => {
+                let style = match name {
+                    "black" => Style::Color(Color::Black),
+                    "red" => Style::Color(Color::Red),
+                    "green" => Style::Color(Color::Green),
+                    "yellow" => Style::Color(Color::Yellow),
+                    "blue" => Style::Color(Color::Blue),
+                    "magenta" => Style::Color(Color::Magenta),
+                    "cyan" => Style::Color(Color::Cyan),
+                    "white" => Style::Color(Color::White),
+                    "grey" => Style::Color(Color::Grey),
+                    "bold" => Style::Bold,
+                    "dim" => Style::Dim,
+                    "reset" => Style::Reset,
+                    _ => bail!("Unknown prompt placeholder {{{name}}}."),
+                };
+                return Ok(Segment::Style(style));
+            }
+        };
+        Ok(Segment::Field(field))
+    }
+}
+
+impl FromStr for PromptTemplate {
+    type Err = Error;
+
+    fn from_str(template: &str) -> Result<Self> {
+        let mut segments = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => bail!("Unclosed {{ in prompt template."),
+                        }
+                    }
+                    let segment = name.parse()?;
+                    if !text.is_empty() {
+                        segments.push(Segment::Text(std::mem::take(&mut text)));
+                    }
+                    segments.push(segment);
+                }
+                '}' => bail!("Unmatched }} in prompt template."),
+                c => text.push(c),
+            }
+        }
+
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+        Ok(Self(segments))
+    }
+}
+
+impl TryFrom<String> for PromptTemplate {
+    type Error = Error;
+
+    fn try_from(template: String) -> Result<Self> {
+        template.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str, info: &PromptInfo) -> String {
+        template.parse::<PromptTemplate>().unwrap().render(info)
+    }
+
+    #[test]
+    fn placeholders() {
+        let info = PromptInfo {
+            index: Some(2),
+            type_name: Some("Ssh"),
+            host: Some("10.0.0.1"),
+            background: 3,
+            ..Default::default()
+        };
+        assert_eq!(
+            render("{session} {type}@{host} [{background}]> ", &info),
+            "2 Ssh@10.0.0.1 [3]> "
+        );
+        assert_eq!(render("{{{index}}}", &info), "{2}");
+
+        let named = PromptInfo {
+            name: Some("web"),
+            ..info
+        };
+        assert_eq!(render("{session}> ", &named), "web> ");
+        assert_eq!(render("{index}{name}> ", &PromptInfo::default()), "> ");
+    }
+
+    #[test]
+    fn colours() {
+        assert_eq!(
+            render("{blue}rctf{reset}> ", &PromptInfo::default()),
+            "\x1b[38;5;12mrctf\x1b[0m> \x1b[0m"
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        assert!("{sesion}".parse::<PromptTemplate>().is_err());
+        assert!("{session".parse::<PromptTemplate>().is_err());
+        assert!("a}b".parse::<PromptTemplate>().is_err());
+    }
+}
@@ -0,0 +1,250 @@
+use std::{borrow::Cow, io::Write};
+
+use anyhow::Result;
+use crossterm::{
+    cursor, queue,
+    style::{self, Stylize},
+    terminal::{self, ClearType},
+};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::terminal::strip_escapes;
+
+/// A screen position relative to the first row of the prompt.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Position {
+    pub row: usize,
+    pub column: usize,
+}
+
+/// Draws a prompt and the line being edited, wrapping it across as many rows as needed.
+///
+/// The renderer remembers which row the cursor was left on so the next draw can start from the
+/// top of the prompt again.
+#[derive(Debug, Default)]
+pub struct Renderer {
+    /// Shown at the start of each row that follows a newline in the text.
+    continuation: String,
+    cursor: Position,
+    end: Position,
+}
+
+impl Renderer {
+    pub fn new(continuation: String) -> Self {
+        Self {
+            continuation,
+            ..Default::default()
+        }
+    }
+
+    /// Redraw `prompt` (which may contain colours) followed by `text`, placing the cursor at the
+    /// byte index `cursor`.
+    pub fn draw(&mut self, prompt: &str, text: &str, cursor: usize) -> Result<()> {
+        let mut stdout = std::io::stdout();
+        let width = terminal::size()
+            .map(|(width, _)| width)
+            .unwrap_or(80)
+            .max(1) as usize;
+        let prompt_width = styled_width(prompt);
+        let continuation_width = styled_width(&self.continuation);
+        let (cursor, end) = layout(prompt_width, continuation_width, text, cursor, width);
+
+        if self.cursor.row > 0 {
+            queue!(stdout, cursor::MoveUp(self.cursor.row as u16))?;
+        }
+        queue!(
+            stdout,
+            cursor::MoveToColumn(0),
+            terminal::Clear(ClearType::FromCursorDown),
+            style::Print(prompt),
+        )?;
+        // Wrap explicitly instead of leaving it to the terminal, so rows always match `layout`.
+        let mut column = prompt_width % width;
+        for grapheme in text.graphemes(true) {
+            if grapheme == "\n" {
+                queue!(
+                    stdout,
+                    style::Print("\r\n"),
+                    style::Print(&self.continuation)
+                )?;
+                column = continuation_width % width;
+                continue;
+            }
+
+            let visible = visible(grapheme);
+            let grapheme_width = visible.width();
+            if column + grapheme_width > width {
+                queue!(stdout, style::Print("\r\n"))?;
+                column = 0;
+            }
+            match visible {
+                Cow::Borrowed(grapheme) => queue!(stdout, style::Print(grapheme))?,
+                Cow::Owned(caret) => queue!(stdout, style::PrintStyledContent(caret.dim()))?,
+            }
+            column += grapheme_width;
+            if column == width {
+                queue!(stdout, style::Print("\r\n"))?;
+                column = 0;
+            }
+        }
+
+        if end.row > cursor.row {
+            queue!(stdout, cursor::MoveUp((end.row - cursor.row) as u16))?;
+        }
+        queue!(stdout, cursor::MoveToColumn(cursor.column as u16))?;
+        stdout.flush()?;
+
+        self.cursor = cursor;
+        self.end = end;
+        Ok(())
+    }
+
+    /// Print `suffix` after the line and move to a fresh row below it, e.g. once it has been
+    /// submitted.
+    pub fn finish(&mut self, suffix: &str) -> Result<()> {
+        let mut stdout = std::io::stdout();
+        if self.end.row > self.cursor.row {
+            queue!(
+                stdout,
+                cursor::MoveDown((self.end.row - self.cursor.row) as u16)
+            )?;
+        }
+        queue!(
+            stdout,
+            cursor::MoveToColumn(self.end.column as u16),
+            style::Print(suffix),
+            style::Print("\r\n")
+        )?;
+        stdout.flush()?;
+
+        self.reset();
+        Ok(())
+    }
+
+    /// Erase the prompt and line, e.g. to print something in their place before redrawing them.
+    pub fn clear(&mut self) -> Result<()> {
+        let mut stdout = std::io::stdout();
+        if self.cursor.row > 0 {
+            queue!(stdout, cursor::MoveUp(self.cursor.row as u16))?;
+        }
+        queue!(
+            stdout,
+            cursor::MoveToColumn(0),
+            terminal::Clear(ClearType::FromCursorDown)
+        )?;
+        stdout.flush()?;
+
+        self.reset();
+        Ok(())
+    }
+
+    /// Forget where the line was drawn, e.g. after the screen has been cleared.
+    pub fn reset(&mut self) {
+        self.cursor = Position::default();
+        self.end = Position::default();
+    }
+}
+
+/// Display width of `text` once any colours have been stripped.
+pub fn styled_width(text: &str) -> usize {
+    display_width(&String::from_utf8_lossy(&strip_escapes(text.as_bytes())))
+}
+
+/// Display width of `text` in terminal columns.
+pub fn display_width(text: &str) -> usize {
+    text.graphemes(true)
+        .map(|grapheme| visible(grapheme).width())
+        .sum()
+}
+
+/// Control characters are shown in caret notation (e.g. `^I` for a tab) so they take up a known
+/// number of columns.
+fn visible(grapheme: &str) -> Cow<'_, str> {
+    match grapheme.as_bytes() {
+        [byte @ (0..=0x1f | 0x7f)] => Cow::Owned(format!("^{}", (byte ^ 0x40) as char)),
+        _ => Cow::Borrowed(grapheme),
+    }
+}
+
+/// Compute where the cursor and the end of the line land when the prompt and `text` are wrapped
+/// to `width` columns. Wide characters that don't fit at the end of a row wrap onto the next, and
+/// rows after a newline start with the continuation prompt.
+pub fn layout(
+    prompt_width: usize,
+    continuation_width: usize,
+    text: &str,
+    cursor: usize,
+    width: usize,
+) -> (Position, Position) {
+    let mut position = Position {
+        row: prompt_width / width,
+        column: prompt_width % width,
+    };
+    let mut cursor_position = None;
+
+    for (i, grapheme) in text.grapheme_indices(true) {
+        if i == cursor {
+            cursor_position = Some(position);
+        }
+
+        if grapheme == "\n" {
+            position.row += 1;
+            position.column = continuation_width % width;
+            continue;
+        }
+
+        let grapheme_width = visible(grapheme).width();
+        if position.column + grapheme_width > width {
+            position.row += 1;
+            position.column = 0;
+            if i == cursor {
+                cursor_position = Some(position);
+            }
+        }
+        position.column += grapheme_width;
+        if position.column == width {
+            position.row += 1;
+            position.column = 0;
+        }
+    }
+
+    (cursor_position.unwrap_or(position), position)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(row: usize, column: usize) -> Position {
+        Position { row, column }
+    }
+
+    #[test]
+    fn single_row() {
+        assert_eq!(layout(6, 0, "ssh", 1, 80), (at(0, 7), at(0, 9)));
+        assert_eq!(layout(6, 0, "", 0, 80), (at(0, 6), at(0, 6)));
+    }
+
+    #[test]
+    fn wrapping() {
+        assert_eq!(layout(4, 0, "abcdefgh", 8, 6), (at(2, 0), at(2, 0)));
+        assert_eq!(layout(4, 0, "abcdefgh", 3, 6), (at(1, 1), at(2, 0)));
+        assert_eq!(layout(4, 0, "abcdefghi", 9, 6), (at(2, 1), at(2, 1)));
+    }
+
+    #[test]
+    fn wide_characters() {
+        // Each CJK character takes two columns and can't be split across rows.
+        assert_eq!(layout(4, 0, "日本", 3, 7), (at(1, 0), at(1, 2)));
+        assert_eq!(layout(0, 0, "é日", 2, 80), (at(0, 1), at(0, 3)));
+        assert_eq!(display_width("a\tb"), 4);
+    }
+
+    #[test]
+    fn multiple_lines() {
+        let text = "printf 'a\nbc'";
+        assert_eq!(layout(6, 2, text, 10, 80), (at(1, 2), at(1, 5)));
+        assert_eq!(layout(6, 2, text, 9, 80), (at(0, 15), at(1, 5)));
+    }
+}
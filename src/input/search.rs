@@ -0,0 +1,135 @@
+/// State of a reverse incremental history search (Ctrl-R).
+#[derive(Debug, Clone, Default)]
+pub struct HistorySearch {
+    query: String,
+    /// The history index and byte offset of the current match.
+    found: Option<(usize, usize)>,
+    failed: bool,
+}
+
+impl HistorySearch {
+    pub fn found(&self) -> Option<(usize, usize)> {
+        self.found
+    }
+
+    pub fn prompt(&self) -> String {
+        let failed = if self.failed { "failed " } else { "" };
+        format!("({failed}reverse-i-search)`{}': ", self.query)
+    }
+
+    pub fn push(&mut self, c: char, history: &[String]) {
+        self.query.push(c);
+        let before = self
+            .found
+            .map(|(index, _)| index + 1)
+            .unwrap_or(history.len());
+        self.search(history, before);
+    }
+
+    pub fn pop(&mut self, history: &[String]) {
+        self.query.pop();
+        self.search(history, history.len());
+    }
+
+    /// Step to the next older match, skipping repeats of the line currently shown.
+    pub fn next(&mut self, history: &[String]) {
+        let Some((mut before, _)) = self.found else {
+            self.search(history, history.len());
+            return;
+        };
+        let current = &history[before];
+
+        loop {
+            let mut search = self.clone();
+            search.search(history, before);
+            match search.found {
+                _ if search.failed => {
+                    self.failed = true;
+                    return;
+                }
+                Some((index, _)) if history[index] == *current => before = index,
+                _ => {
+                    *self = search;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Find the newest entry containing the query, starting from just before `before`.
+    fn search(&mut self, history: &[String], before: usize) {
+        if self.query.is_empty() {
+            self.found = None;
+            self.failed = false;
+            return;
+        }
+
+        let found = history
+            .iter()
+            .enumerate()
+            .take(before)
+            .rev()
+            .find_map(|(index, line)| line.find(&self.query).map(|offset| (index, offset)));
+
+        self.failed = found.is_none();
+        if found.is_some() {
+            self.found = found;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history() -> Vec<String> {
+        Vec::from([
+            "ssh root 10.0.0.1".to_string(),
+            "var set base 0x1000".to_string(),
+            "ssh ctf 10.0.0.2".to_string(),
+            "ssh ctf 10.0.0.2".to_string(),
+            "session".to_string(),
+        ])
+    }
+
+    #[test]
+    fn incremental() {
+        let history = history();
+        let mut search = HistorySearch::default();
+
+        search.push('s', &history);
+        assert_eq!(search.found(), Some((4, 0)));
+        search.push('s', &history);
+        search.push('h', &history);
+        assert_eq!(search.found(), Some((3, 0)));
+
+        search.pop(&history);
+        assert_eq!(search.found(), Some((4, 2)));
+    }
+
+    #[test]
+    fn older_matches() {
+        let history = history();
+        let mut search = HistorySearch::default();
+        for c in "ssh".chars() {
+            search.push(c, &history);
+        }
+
+        search.next(&history);
+        assert_eq!(search.found(), Some((0, 0)));
+        assert!(search.prompt().starts_with("(reverse-i-search)"));
+
+        search.next(&history);
+        assert_eq!(search.found(), Some((0, 0)));
+        assert!(search.prompt().starts_with("(failed reverse-i-search)"));
+    }
+
+    #[test]
+    fn no_match() {
+        let history = history();
+        let mut search = HistorySearch::default();
+        search.push('z', &history);
+        assert_eq!(search.found(), None);
+        assert_eq!(search.prompt(), "(failed reverse-i-search)`z': ");
+    }
+}
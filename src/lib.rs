@@ -1,51 +1,96 @@
-use std::collections::{HashMap, VecDeque};
+use std::{collections::HashMap, path::Path};
 
 use anyhow::Result;
-use session::SessionManager;
+use crossterm::style::Color;
+pub use history::CommandHistory;
+pub use input::Aliases;
+use input::{KillRing, PromptKind};
+use script::OnError;
+use session::{FiredTrigger, Handle, Notifier, SessionManager};
+use settings::Settings;
+use terminal::eprintln_colored;
+use tokio::sync::mpsc;
+use variables::Variables;
 
 pub(crate) mod commands;
 pub mod files;
+pub mod history;
 pub(crate) mod input;
 pub mod rctf;
+pub mod script;
 mod session;
+pub mod settings;
 mod ssh;
 mod termcraft;
 pub(crate) mod terminal;
 pub(crate) mod util;
-
-pub type CommandHistory = VecDeque<String>;
+pub mod variables;
 
 pub struct Context<'a> {
     supports_keyboard_enhancement: bool,
+    settings: Settings,
     sessions: SessionManager<'a>,
-    named_sessions: HashMap<String, usize>,
-    variables: HashMap<String, String>,
+    /// The authoritative mapping of session names.
+    named_sessions: HashMap<String, Handle>,
+    /// The session most recently in the foreground, for switching back to it.
+    last_session: Option<Handle>,
+    variables: Variables,
     rctf_history: CommandHistory,
     termcraft_history: CommandHistory,
+    /// Aliases defined with `alias`, which the config file's aliases take precedence over.
+    aliases: Aliases,
+    kill_ring: KillRing,
+    notifier: Notifier,
+    /// Triggers of any session that need rctf to act, given to each session as it starts.
+    tx_fired: mpsc::UnboundedSender<FiredTrigger>,
+    rx_fired: mpsc::UnboundedReceiver<FiredTrigger>,
 }
 
 impl<'a> Context<'a> {
     pub fn new(
+        settings: Settings,
         rctf_history: Option<CommandHistory>,
         termcraft_history: Option<CommandHistory>,
+        aliases: Option<Aliases>,
     ) -> Result<Self> {
+        let (tx_fired, rx_fired) = mpsc::unbounded_channel();
         Ok(Self {
+            notifier: Notifier::new(&settings.notifications)?,
             supports_keyboard_enhancement: crossterm::terminal::supports_keyboard_enhancement()?,
+            settings,
             sessions: SessionManager::new(), // TODO: restore sessions from files
             named_sessions: HashMap::new(),
-            variables: HashMap::new(), // TODO: restore variables from files
+            last_session: None,
+            variables: Variables::new(), // TODO: restore variables from files
             rctf_history: rctf_history.unwrap_or_default(),
             termcraft_history: termcraft_history.unwrap_or_default(),
+            aliases: aliases.unwrap_or_default(),
+            kill_ring: KillRing::default(),
+            tx_fired,
+            rx_fired,
         })
     }
 
-    pub async fn start(&mut self) -> Result<()> {
+    /// Run the prompt until the user exits, after running a script if one is given.
+    pub async fn start(&mut self, script: Option<(&Path, OnError)>) -> Result<()> {
         terminal::setup(self.supports_keyboard_enhancement)?;
-        let res = self.start_read_loop().await;
+        let res = self.run(script).await;
         terminal::teardown(self.supports_keyboard_enhancement)?;
         res
     }
 
+    async fn run(&mut self, script: Option<(&Path, OnError)>) -> Result<()> {
+        if let Some((path, on_error)) = script {
+            match self.run_script(path, on_error, None).await {
+                Ok(true) => return Ok(()),
+                Ok(false) => {}
+                // A failed script still leaves its sessions to carry on with.
+                Err(e) => eprintln_colored(e, Color::Red)?,
+            }
+        }
+        self.start_read_loop().await
+    }
+
     pub fn rctf_history(&self) -> &CommandHistory {
         &self.rctf_history
     }
@@ -53,4 +98,22 @@ impl<'a> Context<'a> {
     pub fn termcraft_history(&self) -> &CommandHistory {
         &self.termcraft_history
     }
+
+    pub fn aliases(&self) -> &Aliases {
+        &self.aliases
+    }
+
+    fn history(&self, kind: PromptKind) -> &CommandHistory {
+        match kind {
+            PromptKind::Rctf => &self.rctf_history,
+            PromptKind::Termcraft => &self.termcraft_history,
+        }
+    }
+
+    fn history_mut(&mut self, kind: PromptKind) -> &mut CommandHistory {
+        match kind {
+            PromptKind::Rctf => &mut self.rctf_history,
+            PromptKind::Termcraft => &mut self.termcraft_history,
+        }
+    }
 }
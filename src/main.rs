@@ -1,25 +1,40 @@
+use std::path::PathBuf;
+
 use anyhow::Result;
-use rctf::{files::cache, Context};
+use clap::Parser;
+use rctf::{files::cache, script::OnError, settings::Settings, Context};
 
 const RCTF_HISTORY_FILENAME: &str = "rctf.history";
 const TERMCRAFT_HISTORY_FILENAME: &str = "termcraft.history";
+const ALIASES_FILENAME: &str = "aliases.json";
+
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Args {
+    /// Run rctf and termcraft commands from a file before showing the prompt
+    #[arg(long)]
+    script: Option<PathBuf>,
+    /// Whether to stop or carry on when a command in the script fails
+    #[arg(long, value_enum, default_value_t = OnError::Stop, requires = "script")]
+    on_error: OnError,
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // let ssh_settings: Settings = Config::builder()
-    //     .add_source(config::Environment::with_prefix("RCTF"))
-    //     .add_source(config::File::with_name("./rctf.ini"))
-    //     .build()?
-    //     .try_deserialize()?;
+    let args = Args::parse();
+    let settings = Settings::load()?;
 
     let rctf_history = cache::load(RCTF_HISTORY_FILENAME).ok();
     let termcraft_history = cache::load(TERMCRAFT_HISTORY_FILENAME).ok();
+    let aliases = cache::load(ALIASES_FILENAME).ok();
 
-    let mut context = Context::new(rctf_history, termcraft_history)?;
-    context.start().await?;
+    let mut context = Context::new(settings, rctf_history, termcraft_history, aliases)?;
+    let script = args.script.as_deref().map(|path| (path, args.on_error));
+    context.start(script).await?;
 
     cache::save(RCTF_HISTORY_FILENAME, context.rctf_history()).ok();
     cache::save(TERMCRAFT_HISTORY_FILENAME, context.termcraft_history()).ok();
+    cache::save(ALIASES_FILENAME, context.aliases()).ok();
 
     Ok(())
 }
@@ -1,26 +1,28 @@
 use crate::{
     commands::Commands,
-    session::SessionSelection,
+    input::{PromptKind, SESSION_VALUE_NAME},
+    session::{Handle, Session, SessionSelection, SessionState, SessionStats, TagFilter, Tags},
     ssh::{SshSession, SshSettings},
     terminal::{eprintln_colored, println},
-    util::table_settings,
+    util::{format_bytes, format_duration, table_settings},
     Context,
 };
 use anyhow::Result;
-use clap::{arg, command, value_parser, Parser, Subcommand};
+use chrono::{DateTime, Local};
+use clap::{value_parser, Parser, Subcommand, ValueEnum};
 use crossterm::style::Color;
 use tabled::Table;
 
 // TODO: https://docs.rs/clap/latest/clap/_cookbook/repl_derive/index.html
 #[derive(Debug, Parser)]
 #[command(multicall = true)]
-struct Rctf {
+pub(crate) struct Rctf {
     #[command(subcommand)]
-    command: RctfCommands,
+    pub(crate) command: RctfCommands,
 }
 
 #[derive(Debug, Subcommand)]
-enum RctfCommands {
+pub(crate) enum RctfCommands {
     /// SSH into a remote host
     Ssh {
         /// User to connect as
@@ -33,96 +35,471 @@ enum RctfCommands {
         /// Port to use
         #[arg(short, long, default_value_t = 22, value_parser = value_parser!(u16).range(1..))]
         port: u16,
+        /// Connect without resuming the session
+        #[arg(short, long)]
+        background: bool,
     },
-    /// List or use sessions
-    #[group(required = false)]
+    /// List, use or manage sessions
+    #[command(args_conflicts_with_subcommands = true)]
     Session {
-        /// Name of the session to resume
-        name: Option<String>,
-        /// Index of the session to resume
-        index: Option<usize>,
+        #[command(subcommand)]
+        command: Option<SessionCommands>,
+        /// Session to resume, by index (e.g. `1`), handle (e.g. `1.0`), name or tag (e.g. `box=web01`)
+        #[arg(value_name = SESSION_VALUE_NAME)]
+        session: Option<SessionSelection>,
+        /// Only list sessions whose name, type or remote contains this text
+        #[arg(short, long)]
+        filter: Option<String>,
+        /// Only list sessions in this state
+        #[arg(long)]
+        state: Option<SessionState>,
+        /// Only list sessions with this tag, e.g. `box=web01` or `box`; can be repeated
+        #[arg(short, long = "tag", value_name = "TAG")]
+        tags: Vec<TagFilter>,
+        /// Column to sort the list by
+        #[arg(short, long, default_value_t = SessionSort::Index, value_enum)]
+        sort: SessionSort,
+        /// Reverse the sort order
+        #[arg(short, long)]
+        reverse: bool,
     },
 
     #[command(flatten)]
     Command(Commands),
 }
 
+#[derive(Debug, Subcommand)]
+pub(crate) enum SessionCommands {
+    /// Disconnect sessions, keeping them around as closed
+    Kill {
+        #[arg(value_name = SESSION_VALUE_NAME)]
+        session: SessionSelection,
+    },
+    /// Reconnect a closed session and resume it
+    Revive {
+        #[arg(value_name = SESSION_VALUE_NAME)]
+        session: SessionSelection,
+    },
+    /// Remove closed sessions, or all closed sessions
+    Purge {
+        #[arg(value_name = SESSION_VALUE_NAME)]
+        session: Option<SessionSelection>,
+    },
+    /// Change the name of a session
+    Rename {
+        #[arg(value_name = SESSION_VALUE_NAME)]
+        session: SessionSelection,
+        /// The new name
+        name: String,
+    },
+    /// Send a line to sessions without resuming them
+    Send {
+        #[arg(value_name = SESSION_VALUE_NAME)]
+        session: SessionSelection,
+        /// Text to send, which can use variables, expressions and escapes like `printf`
+        text: String,
+        /// Don't append a newline
+        #[arg(short, long)]
+        no_newline: bool,
+    },
+    /// Show details about a session
+    Info {
+        #[arg(value_name = SESSION_VALUE_NAME)]
+        session: SessionSelection,
+    },
+    /// Add tags to sessions, replacing tags with the same name
+    Tag {
+        #[arg(value_name = SESSION_VALUE_NAME)]
+        session: SessionSelection,
+        /// Tags like `box=web01`, or `group=blue` to make the sessions selectable as `@blue`
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+    /// Remove tags from sessions
+    Untag {
+        #[arg(value_name = SESSION_VALUE_NAME)]
+        session: SessionSelection,
+        /// Names of the tags to remove
+        #[arg(required = true)]
+        keys: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum SessionSort {
+    Index,
+    Name,
+    Type,
+    Remote,
+    State,
+    Uptime,
+    In,
+    Out,
+    Activity,
+}
+
+/// Options for listing sessions.
+#[derive(Debug, Clone)]
+struct SessionFilter {
+    filter: Option<String>,
+    state: Option<SessionState>,
+    tags: Vec<TagFilter>,
+    sort: SessionSort,
+    reverse: bool,
+}
+
+impl SessionFilter {
+    fn matches(&self, session: &dyn Session) -> bool {
+        let state = self.state.is_none_or(|state| session.state() == state);
+        let text = self.filter.as_ref().is_none_or(|filter| {
+            [
+                session.name().unwrap_or(""),
+                session.type_name(),
+                &session.remote(),
+            ]
+            .iter()
+            .any(|field| field.contains(filter.as_str()))
+        });
+        let tags = self.tags.iter().all(|tag| session.tags().matches(tag));
+        state && text && tags
+    }
+}
+
+/// A session as shown by `session`.
+struct SessionRow {
+    index: usize,
+    handle: Handle,
+    name: String,
+    type_name: &'static str,
+    remote: String,
+    state: SessionState,
+    tags: Tags,
+    stats: SessionStats,
+}
+
+impl SessionRow {
+    fn new(index: usize, handle: Handle, session: &dyn Session) -> Self {
+        Self {
+            index,
+            handle,
+            name: session.name().unwrap_or("").to_owned(),
+            type_name: session.type_name(),
+            remote: session.remote(),
+            state: session.state(),
+            tags: session.tags().clone(),
+            stats: session.stats(),
+        }
+    }
+
+    fn uptime(&self) -> Option<chrono::Duration> {
+        match self.state {
+            SessionState::Connected => Some(Local::now() - self.stats.connected_at?),
+            SessionState::Disconnected | SessionState::Closed => None,
+        }
+    }
+}
+
 impl<'a> Context<'a> {
     pub async fn start_read_loop(&mut self) -> Result<()> {
-        const PROMPT: &str = env!("CARGO_PKG_NAME");
-
         loop {
-            let mut new_history = self.rctf_history.clone();
-            let res = self.get_next_command(PROMPT, &mut new_history).await;
-            self.rctf_history = new_history;
+            let res = self.get_next_command(PromptKind::Rctf, None).await;
 
             let cmd: Rctf = match res {
                 Ok(Some(cmd)) => cmd,
-                Ok(None) => Rctf { command: RctfCommands::Command(Commands::Exit) },
+                Ok(None) => Rctf {
+                    command: RctfCommands::Command(Commands::Exit),
+                },
                 Err(e) => {
                     eprintln_colored(e, Color::Red)?;
                     continue;
                 }
             };
 
-            match cmd.command {
-                RctfCommands::Ssh {
-                    username,
+            if let RctfCommands::Command(Commands::Exit) = cmd.command {
+                break;
+            }
+            if let Err(e) = self.rctf_command(cmd.command).await {
+                eprintln_colored(e, Color::Red)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run a command from the rctf prompt, other than `exit`.
+    pub(crate) async fn rctf_command(&mut self, command: RctfCommands) -> Result<()> {
+        match command {
+            RctfCommands::Ssh {
+                username,
+                hostname,
+                password,
+                port,
+                background,
+            } => {
+                let settings = SshSettings {
                     hostname,
-                    password,
                     port,
-                } => {
-                    let settings = SshSettings {
-                        hostname,
-                        port,
-                        username,
-                        password: password.unwrap_or(String::new()),
-                    };
-                    let ssh = SshSession::new(settings);
-                    if let Err(e) = self.start_session(ssh).await {
+                    username,
+                    password: password.unwrap_or(String::new()),
+                };
+                let ssh = SshSession::new(settings);
+                self.start_session(ssh, !background).await?;
+            }
+            RctfCommands::Session {
+                command: Some(command),
+                ..
+            } => self.session_command(command).await?,
+            RctfCommands::Session {
+                command: None,
+                session,
+                filter,
+                state,
+                tags,
+                sort,
+                reverse,
+            } => {
+                let filter = SessionFilter {
+                    filter,
+                    state,
+                    tags,
+                    sort,
+                    reverse,
+                };
+                self.session(session, filter).await?;
+            }
+            RctfCommands::Command(command) => {
+                self.handle_command(command, PromptKind::Rctf, None).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn session(
+        &mut self,
+        session: Option<SessionSelection>,
+        filter: SessionFilter,
+    ) -> Result<()> {
+        if let Some(session) = session {
+            self.resume_session(session).await?;
+        } else {
+            self.list_sessions(&filter)?;
+        }
+
+        Ok(())
+    }
+
+    async fn session_command(&mut self, command: SessionCommands) -> Result<()> {
+        match command {
+            SessionCommands::Kill { session } => {
+                for index in self.select_sessions(&session)? {
+                    if let Err(e) = self.close_session(index).await {
                         eprintln_colored(e, Color::Red)?;
                     }
                 }
-                RctfCommands::Session { name, index } => {
-                    if let Err(e) = self.session(name, index).await {
-                        eprintln_colored(e, Color::Red)?;
+            }
+            SessionCommands::Revive { session } => {
+                let index = self.select_session(&session)?;
+                self.revive_session(index).await?;
+            }
+            SessionCommands::Purge {
+                session: Some(session),
+            } => {
+                for index in self.select_sessions(&session)? {
+                    self.purge_session(index)?;
+                }
+            }
+            SessionCommands::Purge { session: None } => {
+                let closed: Vec<_> = self
+                    .sessions
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, session)| {
+                        session
+                            .as_ref()
+                            .is_some_and(|session| session.state() == SessionState::Closed)
+                    })
+                    .map(|(index, _)| index)
+                    .collect();
+                for index in &closed {
+                    self.purge_session(*index)?;
+                }
+                println(format!("Purged {} closed sessions.", closed.len()))?;
+            }
+            SessionCommands::Rename { session, name } => {
+                let index = self.select_session(&session)?;
+                self.rename_session(index, name)?;
+            }
+            SessionCommands::Send {
+                session,
+                text,
+                no_newline,
+            } => {
+                let indices = self.select_sessions(&session)?;
+                let mut data = self.parse_line(&text)?;
+                if !no_newline {
+                    data.push(b'\n');
+                }
+                // One session failing shouldn't keep the text from the rest.
+                for index in indices {
+                    if let Err(e) = self.sessions.get_mut(index).unwrap().send(&data).await {
+                        let label = self.session_label(index);
+                        eprintln_colored(format!("{label}: Failed to send: {e}"), Color::Red)?;
                     }
                 }
-                RctfCommands::Command(Commands::Exit) => break,
-                RctfCommands::Command(command) => {
-                    if let Err(e) = self.handle_command(command).await {
-                        eprintln_colored(e, Color::Red)?;
+            }
+            SessionCommands::Info { session } => {
+                let index = self.select_session(&session)?;
+                self.session_info(index)?;
+            }
+            SessionCommands::Tag { session, tags } => {
+                for index in self.select_sessions(&session)? {
+                    let session_tags = self.sessions.get_mut(index).unwrap().tags_mut();
+                    for tag in &tags {
+                        session_tags.set(tag)?;
                     }
                 }
-            };
+            }
+            SessionCommands::Untag { session, keys } => {
+                for index in self.select_sessions(&session)? {
+                    let session_tags = self.sessions.get_mut(index).unwrap().tags_mut();
+                    for key in &keys {
+                        session_tags.remove(key);
+                    }
+                }
+            }
         }
 
         Ok(())
     }
 
-    async fn session(&mut self, name: Option<String>, index: Option<usize>) -> Result<()> {
-        if let Some(name) = name {
-            self.resume_session(SessionSelection::Name(name)).await?;
-        } else if let Some(index) = index {
-            self.resume_session(SessionSelection::Index(index)).await?;
-        } else {
-            if self.sessions.iter().all(|s| s.is_none()) {
-                println("There are currently no sessions.")?;
-            } else {
-                let mut table = Table::builder(
-                    self.sessions
-                        .iter()
-                        .flatten()
-                        .enumerate()
-                        .map(|(i, s)| (i, s.type_name(), s.name().unwrap_or(""))),
-                );
-                table.set_header(["index", "name", "type"]);
-
-                let table = table.build().with(table_settings()).to_string();
-                println(table)?;
-            }
+    fn session_info(&self, index: usize) -> Result<()> {
+        let session = self.sessions.get(index).unwrap().as_ref();
+        let row = SessionRow::new(index, self.sessions.handle(index).unwrap(), session);
+        let format_time = |time: Option<DateTime<Local>>| {
+            time.map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_default()
+        };
+        let last_line = session.output().borrow().last_line().unwrap_or_default();
+        let exit = session
+            .exit()
+            .borrow()
+            .as_ref()
+            .map(ToString::to_string)
+            .unwrap_or_default();
+        let transcript = session
+            .transcript()
+            .path()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default();
+
+        let mut table = Table::builder([
+            ["index", &row.index.to_string()],
+            ["handle", &row.handle.to_string()],
+            ["name", &row.name],
+            ["type", row.type_name],
+            ["remote", &row.remote],
+            ["state", &row.state.to_string()],
+            ["tags", &row.tags.to_string()],
+            ["exit status", &exit],
+            ["connected at", &format_time(row.stats.connected_at)],
+            [
+                "uptime",
+                &row.uptime().map(format_duration).unwrap_or_default(),
+            ],
+            ["in", &format_bytes(row.stats.bytes_in)],
+            ["out", &format_bytes(row.stats.bytes_out)],
+            ["last activity", &format_time(row.stats.last_activity)],
+            ["last line", &last_line],
+            ["triggers", &session.triggers().list().len().to_string()],
+            [
+                "prompt",
+                &session
+                    .prompt(&self.settings.session)
+                    .map(|prompt| prompt.to_string())
+                    .unwrap_or_default(),
+            ],
+            ["transcript", &transcript],
+        ]);
+        table.set_header(["property", "value"]);
+
+        let table = table.build().with(table_settings()).to_string();
+        println(table)?;
+
+        Ok(())
+    }
+
+    fn list_sessions(&self, filter: &SessionFilter) -> Result<()> {
+        if self.sessions.iter().all(|session| session.is_none()) {
+            println("There are currently no sessions.")?;
+            return Ok(());
+        }
+
+        let mut rows: Vec<_> = self
+            .sessions
+            .iter()
+            .enumerate()
+            .filter_map(|(index, session)| Some((index, session.as_deref()?)))
+            .filter(|(_, session)| filter.matches(*session))
+            .map(|(index, session)| {
+                SessionRow::new(index, self.sessions.handle(index).unwrap(), session)
+            })
+            .collect();
+        if rows.is_empty() {
+            println("No sessions match.")?;
+            return Ok(());
         }
 
+        match filter.sort {
+            SessionSort::Index => rows.sort_by_key(|row| row.index),
+            SessionSort::Name => rows.sort_by(|a, b| a.name.cmp(&b.name)),
+            SessionSort::Type => rows.sort_by_key(|row| row.type_name),
+            SessionSort::Remote => rows.sort_by(|a, b| a.remote.cmp(&b.remote)),
+            SessionSort::State => rows.sort_by_key(|row| row.state),
+            SessionSort::Uptime => rows.sort_by_key(SessionRow::uptime),
+            SessionSort::In => rows.sort_by_key(|row| row.stats.bytes_in),
+            SessionSort::Out => rows.sort_by_key(|row| row.stats.bytes_out),
+            SessionSort::Activity => rows.sort_by_key(|row| row.stats.last_activity),
+        }
+        if filter.reverse {
+            rows.reverse();
+        }
+
+        let mut table = Table::builder(rows.iter().map(|row| {
+            [
+                row.index.to_string(),
+                row.handle.to_string(),
+                row.name.clone(),
+                row.type_name.to_owned(),
+                row.remote.clone(),
+                row.state.to_string(),
+                row.tags.to_string(),
+                row.uptime().map(format_duration).unwrap_or_default(),
+                format_bytes(row.stats.bytes_in),
+                format_bytes(row.stats.bytes_out),
+                row.stats
+                    .last_activity
+                    .map(|time| time.format("%H:%M:%S").to_string())
+                    .unwrap_or_default(),
+            ]
+        }));
+        table.set_header([
+            "index",
+            "handle",
+            "name",
+            "type",
+            "remote",
+            "state",
+            "tags",
+            "uptime",
+            "in",
+            "out",
+            "last activity",
+        ]);
+
+        let table = table.build().with(table_settings()).to_string();
+        println(table)?;
+
         Ok(())
     }
 }
@@ -0,0 +1,251 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context as _, Result};
+use clap::{CommandFactory, Parser, ValueEnum};
+use crossterm::style::Color;
+use futures::{future::LocalBoxFuture, FutureExt};
+
+use crate::{
+    commands::Commands,
+    input::{is_incomplete, PromptKind},
+    rctf::{Rctf, RctfCommands},
+    session::{Handle, SessionSelection, SessionState},
+    termcraft::{Termcraft, TermcraftResponse},
+    terminal::eprintln_colored,
+    Context,
+};
+
+mod engine;
+pub(crate) use self::engine::user_script;
+
+/// What to do when a command in a script fails.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OnError {
+    /// Stop running the script
+    #[default]
+    Stop,
+    /// Report the error and carry on with the next command
+    Continue,
+}
+
+/// The session that termcraft commands in a script apply to. It is kept as a handle so that a
+/// script never carries on with a different session that took over its index.
+struct ScriptSession {
+    handle: Handle,
+    /// How far `expect` and friends have read the session's output.
+    position: usize,
+}
+
+impl<'a> Context<'a> {
+    /// Run the commands in a script, starting on a session if one is given, and return whether
+    /// the script ran `exit`.
+    pub(crate) fn run_script<'b>(
+        &'b mut self,
+        path: &'b Path,
+        on_error: OnError,
+        session_index: Option<usize>,
+    ) -> LocalBoxFuture<'b, Result<bool>> {
+        // Boxed because scripts can `source` other scripts.
+        async move {
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}.", path.display()))?;
+            let mut session = match session_index {
+                Some(index) => Some(ScriptSession {
+                    handle: self.session_handle(index)?,
+                    position: self.written(index)?,
+                }),
+                None => None,
+            };
+
+            for (line_number, line) in lines(&text) {
+                match self.run_script_line(&line, &mut session).await {
+                    Ok(true) => return Ok(true),
+                    Ok(false) => {}
+                    Err(e) => {
+                        let e = anyhow!("{}:{line_number}: {e}", path.display());
+                        match on_error {
+                            OnError::Stop => return Err(e),
+                            OnError::Continue => eprintln_colored(e, Color::Red)?,
+                        }
+                    }
+                }
+            }
+
+            Ok(false)
+        }
+        .boxed_local()
+    }
+
+    /// Run a line of a script as an rctf command, or as a termcraft command on the script's
+    /// session, returning whether it was `exit`.
+    async fn run_script_line(
+        &mut self,
+        line: &str,
+        session: &mut Option<ScriptSession>,
+    ) -> Result<bool> {
+        let Some(args) = shlex::split(line) else {
+            bail!("Invalid quoting.");
+        };
+        let args = self.expand_alias(args)?;
+        let Some(name) = args.first() else {
+            return Ok(false);
+        };
+        let index = match session {
+            Some(session) => Some(self.resolve_handle(session.handle)?),
+            None => None,
+        };
+        if let Some(path) = script_command(name) {
+            self.run_rhai(&path, args[1..].to_vec(), index).await?;
+            return Ok(false);
+        }
+        let command = match Rctf::try_parse_from(&args) {
+            Ok(cmd) => cmd.command,
+            Err(e) => {
+                // Mistakes in rctf commands are reported as such.
+                let rctf = Rctf::command().find_subcommand(name).is_some();
+                let (false, Some(index), Some(ScriptSession { position, .. })) =
+                    (rctf, index, session)
+                else {
+                    return Err(e.into());
+                };
+                let command = Termcraft::try_parse_from(&args)?.command;
+                if let Some(TermcraftResponse::Cmd(data)) =
+                    self.termcraft_command(index, command, position).await?
+                {
+                    let Some(session) = self.sessions.get_mut(index) else {
+                        bail!("Could not find session with index {index}.");
+                    };
+                    session.send(&[data.as_slice(), b"\n"].concat()).await?;
+                }
+                return Ok(false);
+            }
+        };
+
+        match command {
+            RctfCommands::Command(Commands::Exit) => return Ok(true),
+            RctfCommands::Ssh {
+                username,
+                hostname,
+                password,
+                port,
+                ..
+            } => {
+                // Scripts can't hand the terminal over to a session.
+                let index = self.sessions.next_index();
+                self.rctf_command(RctfCommands::Ssh {
+                    username,
+                    hostname,
+                    password,
+                    port,
+                    background: true,
+                })
+                .await?;
+                *session = Some(ScriptSession {
+                    handle: self.session_handle(index)?,
+                    position: 0,
+                });
+            }
+            RctfCommands::Session {
+                command: None,
+                session: Some(selection),
+                ..
+            } => {
+                let index = self.select_session(&selection)?;
+                if self.sessions.get(index).unwrap().state() == SessionState::Closed {
+                    bail!("Session {selection} is closed.");
+                }
+                *session = Some(ScriptSession {
+                    handle: self.session_handle(index)?,
+                    position: self.written(index)?,
+                });
+            }
+            RctfCommands::Command(command) => {
+                self.handle_command(command, PromptKind::Rctf, index)
+                    .await?;
+            }
+            command => self.rctf_command(command).await?,
+        }
+
+        Ok(false)
+    }
+
+    fn session_handle(&self, session_index: usize) -> Result<Handle> {
+        let Some(handle) = self.sessions.handle(session_index) else {
+            bail!("Could not find session with index {session_index}.");
+        };
+        Ok(handle)
+    }
+
+    /// The session's index, as long as it is still the same session.
+    fn resolve_handle(&self, handle: Handle) -> Result<usize> {
+        self.select_session(&SessionSelection::Handle(handle))
+    }
+
+    fn written(&self, session_index: usize) -> Result<usize> {
+        let Some(session) = self.sessions.get(session_index) else {
+            bail!("Could not find session with index {session_index}.");
+        };
+        let written = session.output().borrow().written();
+        Ok(written)
+    }
+}
+
+/// The script to run for a command that neither prompt has.
+fn script_command(name: &str) -> Option<PathBuf> {
+    let builtin = Rctf::command().find_subcommand(name).is_some()
+        || Termcraft::command().find_subcommand(name).is_some();
+    if builtin {
+        return None;
+    }
+    user_script(name)
+}
+
+/// Split a script into commands along with their line numbers, skipping blank lines and
+/// `#` comments. Like at the prompt, an unterminated quote or a trailing backslash continues a
+/// command onto the next line.
+fn lines(text: &str) -> Vec<(usize, String)> {
+    let mut commands = Vec::new();
+    let mut pending: Option<(usize, String)> = None;
+    for (i, line) in text.lines().enumerate() {
+        let (start, command) = match pending.take() {
+            Some((start, command)) => (start, command + line),
+            None if line.trim().is_empty() || line.trim_start().starts_with('#') => continue,
+            None => (i + 1, line.to_owned()),
+        };
+        if let Some(command) = command
+            .strip_suffix('\\')
+            .filter(|command| !is_incomplete(command))
+        {
+            pending = Some((start, format!("{command} ")));
+        } else if is_incomplete(&command) {
+            pending = Some((start, command + "\n"));
+        } else {
+            commands.push((start, command));
+        }
+    }
+    commands.extend(pending);
+    commands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script_lines() {
+        let script = "# setup\nssh root box \\\n  --password hunter2\n\n  var set x 'a\nb'\nexit";
+        assert_eq!(
+            lines(script),
+            [
+                (2, "ssh root box    --password hunter2".to_string()),
+                (5, "  var set x 'a\nb'".to_string()),
+                (7, "exit".to_string()),
+            ]
+        );
+        assert_eq!(
+            shlex::split(&lines(script)[0].1).unwrap(),
+            ["ssh", "root", "box", "--password", "hunter2"]
+        );
+        assert_eq!(lines("printf 'id")[0].0, 1);
+    }
+}
@@ -0,0 +1,548 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::{anyhow, bail, Context as _, Result};
+use clap::Parser;
+use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyModifiers};
+use futures::{future::LocalBoxFuture, FutureExt, StreamExt};
+use rhai::{Array, Blob, Dynamic, Engine, EvalAltResult, Scope, INT};
+use tabled::Table;
+use tokio::{
+    select,
+    sync::{mpsc, oneshot},
+    task,
+};
+
+use crate::{
+    commands::Commands,
+    files,
+    rctf::{Rctf, RctfCommands},
+    session::Handle,
+    ssh::{SshSession, SshSettings},
+    termcraft::{literal, DEFAULT_TIMEOUT},
+    terminal::println,
+    util::table_settings,
+    variables::{expr, Value},
+    Context,
+};
+
+/// Subdirectory of the config directory with the scripts that can be run by name.
+const SCRIPTS_DIR: &str = "scripts";
+const SCRIPT_EXTENSION: &str = "rhai";
+/// Functions from expressions that scripts can call too, e.g. `p64(addr)`.
+const ENCODERS: [&str; 9] = ["p8", "p16", "p32", "p64", "u8", "u16", "u32", "u64", "hex"];
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// Something a script needs rctf to do, since only rctf's own task can use the sessions.
+#[derive(Debug)]
+enum Request {
+    Ssh {
+        username: String,
+        hostname: String,
+        password: String,
+        port: u16,
+    },
+    Send {
+        session: Handle,
+        data: Vec<u8>,
+    },
+    Expect {
+        session: Handle,
+        regex: String,
+        timeout: f64,
+    },
+    Recvuntil {
+        session: Handle,
+        delimiter: Vec<u8>,
+        timeout: f64,
+    },
+    GetVar(String),
+    SetVar(String, Value),
+    Run(String),
+    Print(String),
+}
+
+/// What rctf answers a request with, if anything.
+#[derive(Debug)]
+enum Reply {
+    Value(Value),
+    /// Sessions are given to scripts as handles, so that a script can't use a different
+    /// session that has taken over the index of one it started with.
+    Session(Handle),
+}
+
+struct Call {
+    request: Request,
+    tx_reply: oneshot::Sender<Result<Option<Reply>>>,
+}
+
+impl<'a> Context<'a> {
+    /// Run a rhai script, carrying out its requests until it finishes. `session_index` is the
+    /// session termcraft is open on, if any, which the script sees as `SESSION`.
+    pub(crate) fn run_rhai<'b>(
+        &'b mut self,
+        path: &'b Path,
+        args: Vec<String>,
+        session_index: Option<usize>,
+    ) -> LocalBoxFuture<'b, Result<()>> {
+        // Boxed because scripts can run other scripts.
+        async move {
+            let source = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}.", path.display()))?;
+            let session = match session_index {
+                Some(index) => Some(self.session_handle(index)?),
+                None => None,
+            };
+            let (tx_call, mut rx_call) = mpsc::unbounded_channel();
+            let cancelled = Arc::new(AtomicBool::new(false));
+            let mut script = task::spawn_blocking({
+                let cancelled = cancelled.clone();
+                move || run(&source, args, session, tx_call, cancelled)
+            });
+
+            // The terminal is in raw mode, so Ctrl-C has to be read like any other key.
+            let mut events = EventStream::new();
+            // How far `expect` and `recvuntil` have read each session's output.
+            let mut positions = HashMap::new();
+            // Triggers other than responses wait until the script is done.
+            let mut deferred = Vec::new();
+            let res = loop {
+                select! {
+                    Some(call) = rx_call.recv() => {
+                        // Commands run by the script read keys themselves, e.g. a resumed session.
+                        let reply = if matches!(call.request, Request::Run(_)) {
+                            self.script_request(call.request, &mut positions).await
+                        } else {
+                            select! {
+                                reply = self.script_request(call.request, &mut positions) => reply,
+                                _ = interrupted(&mut events) => {
+                                    cancelled.store(true, Ordering::Relaxed);
+                                    Err(anyhow!("Interrupted."))
+                                }
+                            }
+                        };
+                        call.tx_reply.send(reply).ok();
+                    }
+                    Some(fired) = self.rx_fired.recv() => {
+                        deferred.extend(self.answer_trigger(fired).await?);
+                    }
+                    _ = interrupted(&mut events) => cancelled.store(true, Ordering::Relaxed),
+                    res = &mut script => break res,
+                }
+            };
+            for fired in deferred {
+                self.tx_fired.send(fired).ok();
+            }
+            res?
+        }
+        .boxed_local()
+    }
+
+    async fn script_request(
+        &mut self,
+        request: Request,
+        positions: &mut HashMap<Handle, usize>,
+    ) -> Result<Option<Reply>> {
+        let value = match request {
+            Request::Ssh {
+                username,
+                hostname,
+                password,
+                port,
+            } => {
+                let ssh = SshSession::new(SshSettings {
+                    hostname,
+                    port,
+                    username,
+                    password,
+                });
+                let index = self.start_session(ssh, false).await?;
+                let handle = self.session_handle(index)?;
+                positions.insert(handle, 0);
+                return Ok(Some(Reply::Session(handle)));
+            }
+            Request::Send { session, data } => {
+                let index = self.resolve_handle(session)?;
+                let Some(session) = self.sessions.get_mut(index) else {
+                    bail!("Could not find session with index {index}.");
+                };
+                session.send(&data).await?;
+                None
+            }
+            Request::Expect {
+                session,
+                regex,
+                timeout,
+            } => {
+                let regex = regex::bytes::Regex::new(&regex)?;
+                let index = self.resolve_handle(session)?;
+                let mut position = self.position(positions, session, index)?;
+                let received = self
+                    .receive_until(index, &mut position, &regex, timeout)
+                    .await?;
+                positions.insert(session, position);
+                Some(Value::Str(received.matched))
+            }
+            Request::Recvuntil {
+                session,
+                delimiter,
+                timeout,
+            } => {
+                let regex = literal(&delimiter)?;
+                let index = self.resolve_handle(session)?;
+                let mut position = self.position(positions, session, index)?;
+                let received = self
+                    .receive_until(index, &mut position, &regex, timeout)
+                    .await?;
+                positions.insert(session, position);
+                Some(Value::Str(received.data))
+            }
+            Request::GetVar(name) => self.variables.get(&name).cloned(),
+            Request::SetVar(name, value) => {
+                self.variables.insert(name, value);
+                None
+            }
+            Request::Run(line) => {
+                let Some(args) = shlex::split(&line) else {
+                    bail!("Invalid quoting.");
+                };
+                match Rctf::try_parse_from(args)?.command {
+                    RctfCommands::Command(Commands::Exit) => bail!("Scripts can't exit rctf."),
+                    command => self.rctf_command(command).await?,
+                }
+                None
+            }
+            Request::Print(text) => {
+                println(text)?;
+                None
+            }
+        };
+
+        Ok(value.map(Reply::Value))
+    }
+
+    /// Where a script is reading a session's output from, starting with what is already shown.
+    fn position(
+        &self,
+        positions: &HashMap<Handle, usize>,
+        handle: Handle,
+        session_index: usize,
+    ) -> Result<usize> {
+        match positions.get(&handle) {
+            Some(position) => Ok(*position),
+            None => self.written(session_index),
+        }
+    }
+
+    pub(crate) fn list_scripts(&self) -> Result<()> {
+        let Some(dir) = files::config::create_path(SCRIPTS_DIR) else {
+            bail!("Failed to get the config directory.");
+        };
+        let mut scripts: Vec<_> = std::fs::read_dir(&dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if path.extension()? != SCRIPT_EXTENSION {
+                    return None;
+                }
+                let name = path.file_stem()?.to_str()?.to_owned();
+                Some([name, path.display().to_string()])
+            })
+            .collect();
+        if scripts.is_empty() {
+            println(format!(
+                "No scripts found, add them to {} to run them by name.",
+                dir.display()
+            ))?;
+            return Ok(());
+        }
+        scripts.sort();
+
+        let mut table = Table::builder(scripts);
+        table.set_header(["name", "path"]);
+
+        let table = table.build().with(table_settings()).to_string();
+        println(table)?;
+
+        Ok(())
+    }
+}
+
+/// The script that runs as the command `name`, if there is one.
+pub(crate) fn user_script(name: &str) -> Option<PathBuf> {
+    if name.starts_with('.') || name.contains(std::path::is_separator) {
+        return None;
+    }
+    let path = files::config::create_path(SCRIPTS_DIR)?.join(format!("{name}.{SCRIPT_EXTENSION}"));
+    path.is_file().then_some(path)
+}
+
+/// Wait for Ctrl-C, ignoring any other input.
+async fn interrupted(events: &mut EventStream) {
+    loop {
+        match events.next().await {
+            Some(Ok(Event::Key(KeyEvent {
+                code: KeyCode::Char('c'),
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            }))) => return,
+            Some(Ok(_)) => continue,
+            Some(Err(_)) | None => std::future::pending().await,
+        }
+    }
+}
+
+/// Run a script on this thread, sending requests to rctf and waiting for the replies.
+fn run(
+    source: &str,
+    args: Vec<String>,
+    session: Option<Handle>,
+    tx_call: mpsc::UnboundedSender<Call>,
+    cancelled: Arc<AtomicBool>,
+) -> Result<()> {
+    let mut engine = Engine::new();
+    register(&mut engine, Requests(tx_call));
+    engine.on_progress(move |_| {
+        cancelled
+            .load(Ordering::Relaxed)
+            .then(|| "Interrupted.".into())
+    });
+
+    let mut scope = Scope::new();
+    let args: Array = args.into_iter().map(Dynamic::from).collect();
+    scope.push_constant("ARGS", args);
+    scope.push_constant("SESSION", session.map_or(Dynamic::UNIT, Dynamic::from));
+
+    engine
+        .run_with_scope(&mut scope, source)
+        .map_err(|e| anyhow!("{e}"))
+}
+
+#[derive(Clone)]
+struct Requests(mpsc::UnboundedSender<Call>);
+
+impl Requests {
+    fn call(&self, request: Request) -> ScriptResult<Dynamic> {
+        let (tx_reply, rx_reply) = oneshot::channel();
+        self.0
+            .send(Call { request, tx_reply })
+            .map_err(|_| "rctf stopped running the script.")?;
+        match rx_reply.blocking_recv() {
+            Ok(Ok(Some(Reply::Value(value)))) => to_dynamic(value),
+            Ok(Ok(Some(Reply::Session(handle)))) => Ok(Dynamic::from(handle)),
+            Ok(Ok(None)) => Ok(Dynamic::UNIT),
+            Ok(Err(e)) => Err(e.to_string().into()),
+            Err(_) => Err("rctf stopped running the script.".into()),
+        }
+    }
+}
+
+/// Add the session API to the engine.
+fn register(engine: &mut Engine, requests: Requests) {
+    // Sessions show up as their handle, e.g. in `run("session kill " + s)`.
+    engine
+        .register_type_with_name::<Handle>("Session")
+        .register_fn("to_string", |handle: &mut Handle| handle.to_string())
+        .register_fn("to_debug", |handle: &mut Handle| handle.to_string());
+
+    let r = requests.clone();
+    engine.on_print(move |text| {
+        r.call(Request::Print(text.to_owned())).ok();
+    });
+
+    let r = requests.clone();
+    let ssh = move |username: &str, hostname: &str, password: &str, port: INT| {
+        let Ok(port) = u16::try_from(port) else {
+            return Err(format!("Invalid port {port}.").into());
+        };
+        r.call(Request::Ssh {
+            username: username.to_owned(),
+            hostname: hostname.to_owned(),
+            password: password.to_owned(),
+            port,
+        })
+    };
+    let default = ssh.clone();
+    engine.register_fn("ssh", move |username: &str, hostname: &str| {
+        default(username, hostname, "", 22)
+    });
+    let default = ssh.clone();
+    engine.register_fn(
+        "ssh",
+        move |username: &str, hostname: &str, password: &str| {
+            default(username, hostname, password, 22)
+        },
+    );
+    engine.register_fn("ssh", ssh);
+
+    for (name, newline) in [("send", false), ("sendline", true)] {
+        let r = requests.clone();
+        engine.register_fn(name, move |session: Handle, data: Dynamic| {
+            let mut data = from_dynamic(data)?.to_bytes();
+            if newline {
+                data.push(b'\n');
+            }
+            r.call(Request::Send { session, data })
+        });
+    }
+
+    let r = requests.clone();
+    let expect = move |session: Handle, regex: &str, timeout: Dynamic| {
+        r.call(Request::Expect {
+            session,
+            regex: regex.to_owned(),
+            timeout: seconds(timeout)?,
+        })
+    };
+    let default = expect.clone();
+    engine.register_fn("expect", move |session: Handle, regex: &str| {
+        default(session, regex, Dynamic::from_float(DEFAULT_TIMEOUT))
+    });
+    engine.register_fn("expect", expect);
+
+    let r = requests.clone();
+    let recvuntil = move |session: Handle, delimiter: Dynamic, timeout: Dynamic| {
+        r.call(Request::Recvuntil {
+            session,
+            delimiter: from_dynamic(delimiter)?.to_bytes(),
+            timeout: seconds(timeout)?,
+        })
+    };
+    let default = recvuntil.clone();
+    engine.register_fn("recvuntil", move |session: Handle, delimiter: Dynamic| {
+        default(session, delimiter, Dynamic::from_float(DEFAULT_TIMEOUT))
+    });
+    engine.register_fn("recvuntil", recvuntil);
+
+    let r = requests.clone();
+    engine.register_fn("get_var", move |name: &str| {
+        r.call(Request::GetVar(name.to_owned()))
+    });
+    let r = requests.clone();
+    engine.register_fn("set_var", move |name: &str, value: Dynamic| {
+        r.call(Request::SetVar(name.to_owned(), from_dynamic(value)?))
+    });
+    let r = requests;
+    engine.register_fn("run", move |line: &str| {
+        r.call(Request::Run(line.to_owned()))
+    });
+
+    for name in ENCODERS {
+        engine.register_fn(name, move |value: Dynamic| {
+            let value = expr::call(name, vec![from_dynamic(value)?]).map_err(|e| e.to_string())?;
+            to_dynamic(value)
+        });
+    }
+}
+
+fn seconds(timeout: Dynamic) -> ScriptResult<f64> {
+    if let Ok(int) = timeout.as_int() {
+        return Ok(int as f64);
+    }
+    timeout
+        .as_float()
+        .map_err(|_| "The timeout must be a number of seconds.".into())
+}
+
+fn to_dynamic(value: Value) -> ScriptResult<Dynamic> {
+    let dynamic = match value {
+        Value::Int(int) => match INT::try_from(int) {
+            Ok(int) => Dynamic::from_int(int),
+            Err(_) => return Err(format!("{int} is too big for a script.").into()),
+        },
+        Value::Str(str) => str.into(),
+        Value::Bytes(bytes) => Dynamic::from_blob(bytes),
+        Value::List(list) => list
+            .into_iter()
+            .map(to_dynamic)
+            .collect::<ScriptResult<Array>>()?
+            .into(),
+    };
+    Ok(dynamic)
+}
+
+fn from_dynamic(value: Dynamic) -> ScriptResult<Value> {
+    let type_name = value.type_name();
+    if let Ok(int) = value.as_int() {
+        return Ok(Value::Int(int.into()));
+    }
+    if value.is::<Blob>() {
+        return Ok(Value::Bytes(value.cast::<Blob>()));
+    }
+    if value.is_array() {
+        let list = value.cast::<Array>().into_iter().map(from_dynamic);
+        return Ok(Value::List(list.collect::<ScriptResult<_>>()?));
+    }
+    match value.into_string() {
+        Ok(str) => Ok(Value::Str(str)),
+        Err(_) => Err(format!("Scripts can't use a {type_name} as a value.").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values() {
+        let value = Value::List(vec![
+            Value::Int(-1),
+            Value::Str("id".to_string()),
+            Value::Bytes(b"\x00\xff".to_vec()),
+        ]);
+        assert_eq!(
+            from_dynamic(to_dynamic(value.clone()).unwrap()).unwrap(),
+            value
+        );
+        assert!(to_dynamic(Value::Int(i128::MAX)).is_err());
+        assert!(from_dynamic(Dynamic::from_float(1.5)).is_err());
+    }
+
+    #[test]
+    fn encoders() {
+        let mut engine = Engine::new();
+        let (tx_call, _rx_call) = mpsc::unbounded_channel();
+        register(&mut engine, Requests(tx_call));
+        let packed: Blob = engine.eval("p32(0x41424344)").unwrap();
+        assert_eq!(packed, b"DCBA");
+        let unpacked: INT = engine.eval("u16(p16(513))").unwrap();
+        assert_eq!(unpacked, 513);
+        assert!(engine.eval::<Blob>("p8(\"x\")").is_err());
+    }
+
+    #[test]
+    fn sessions() {
+        let mut engine = Engine::new();
+        let (tx_call, _rx_call) = mpsc::unbounded_channel();
+        register(&mut engine, Requests(tx_call));
+        let mut scope = Scope::new();
+        let handle = Handle {
+            index: 1,
+            generation: 2,
+        };
+        scope.push_constant("SESSION", Dynamic::from(handle));
+        let command: String = engine
+            .eval_with_scope(&mut scope, r#""session tag " + SESSION + " solved""#)
+            .unwrap();
+        assert_eq!(command, "session tag 1.2 solved");
+        // Plain indices could refer to a different session by the time they are used.
+        assert!(engine
+            .run_with_scope(&mut scope, r#"sendline(1, "id")"#)
+            .is_err());
+    }
+
+    #[test]
+    fn interrupts() {
+        let (tx_call, _rx_call) = mpsc::unbounded_channel();
+        let cancelled = Arc::new(AtomicBool::new(true));
+        assert!(run("loop {}", Vec::new(), None, tx_call, cancelled).is_err());
+    }
+}
@@ -1,10 +1,37 @@
-use anyhow::{bail, Result};
+use std::{fmt::Display, str::FromStr};
+
+use anyhow::{bail, Error, Result};
 use async_trait::async_trait;
+use chrono::{DateTime, Local};
+use clap::ValueEnum;
+use tokio::sync::{mpsc, watch};
+
+use crossterm::style::Color;
 
-use crate::{termcraft::TermcraftResponse, terminal::println, Context};
+use crate::{
+    settings::SessionSettings,
+    termcraft::TermcraftResponse,
+    terminal::{eprintln_colored, println, println_colored},
+    Context,
+};
 
+mod notifier;
+mod output;
+mod prompt;
+mod recording;
 mod stable_vec;
+mod tags;
+mod transcript;
+mod triggers;
+pub use self::notifier::Notifier;
+pub use self::output::OutputBuffer;
+pub use self::prompt::{wait_for_prompt, PromptPattern};
+pub use self::recording::{CastEvent, CastHeader, Recording};
+pub use self::stable_vec::Handle;
 use self::stable_vec::StableVec;
+pub use self::tags::{TagFilter, Tags};
+pub use self::transcript::Transcript;
+pub use self::triggers::{FiredTrigger, Trigger, TriggerAction, Triggers};
 
 pub type SessionManager<'a> = StableVec<Box<dyn Session + 'a>>;
 
@@ -12,12 +39,108 @@ pub type SessionManager<'a> = StableVec<Box<dyn Session + 'a>>;
 pub enum SessionExit {
     Termcraft,
     Exit,
+    /// Switch straight to another session using the prefix key.
+    Switch(SwitchTarget),
+    /// A trigger of this or another session needs rctf to act.
+    Trigger(FiredTrigger),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwitchTarget {
+    Index(usize),
+    Next,
+    Previous,
+    /// The session that was in the foreground before this one.
+    Last,
+}
+
+/// A session as written on the command line: an index like `1` for humans, a handle like `1.0`
+/// that never refers to a different session once its own has gone, or a name. A tag like
+/// `box=web01`, or a group like `@blue` (short for `group=blue`), can select several sessions.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum SessionSelection {
     Index(usize),
+    Handle(Handle),
     Name(String),
+    Tag(TagFilter),
+}
+
+impl FromStr for SessionSelection {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Ok(index) = s.parse() {
+            Ok(SessionSelection::Index(index))
+        } else if let Ok(handle) = s.parse() {
+            Ok(SessionSelection::Handle(handle))
+        } else if let Some(group) = s.strip_prefix('@').filter(|group| !group.is_empty()) {
+            Ok(SessionSelection::Tag(TagFilter::group(group.to_owned())))
+        } else if s.contains('=') {
+            Ok(SessionSelection::Tag(s.parse()?))
+        } else {
+            Ok(SessionSelection::Name(s.to_owned()))
+        }
+    }
+}
+
+impl Display for SessionSelection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionSelection::Index(index) => write!(f, "{index}"),
+            SessionSelection::Handle(handle) => write!(f, "{handle}"),
+            SessionSelection::Name(name) => write!(f, "{name}"),
+            SessionSelection::Tag(filter) => write!(f, "{filter}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum SessionState {
+    Connected,
+    Disconnected,
+    /// The remote end went away; the session can be revived or purged.
+    Closed,
+}
+
+impl Display for SessionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionState::Connected => write!(f, "connected"),
+            SessionState::Disconnected => write!(f, "disconnected"),
+            SessionState::Closed => write!(f, "closed"),
+        }
+    }
+}
+
+/// How a session's remote end went away.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExitStatus {
+    Code(u32),
+    Signal(String, String),
+    /// The connection closed without reporting an exit status.
+    Closed,
+}
+
+impl Display for ExitStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExitStatus::Code(code) => write!(f, "Process exited with code {code}."),
+            ExitStatus::Signal(signal, reason) => {
+                write!(f, "Process exited with signal SIG{signal}: {reason}")
+            }
+            ExitStatus::Closed => write!(f, "Connection closed."),
+        }
+    }
+}
+
+/// Traffic and timing information about a session.
+#[derive(Debug, Clone, Default)]
+pub struct SessionStats {
+    pub connected_at: Option<DateTime<Local>>,
+    pub bytes_in: usize,
+    pub bytes_out: usize,
+    /// When data was last sent or received.
+    pub last_activity: Option<DateTime<Local>>,
 }
 
 #[async_trait]
@@ -25,54 +148,287 @@ pub trait Session {
     fn type_name(&self) -> &'static str;
 
     async fn connect(&mut self) -> Result<()>;
-    async fn start_read_loop(&mut self) -> Result<SessionExit>;
-    async fn reset_prompt(&mut self) -> Result<()>;
+    async fn start_read_loop(
+        &mut self,
+        settings: &SessionSettings,
+        rx_fired: &mut mpsc::UnboundedReceiver<FiredTrigger>,
+    ) -> Result<SessionExit>;
+    /// Interrupt whatever is running and wait for the shell prompt.
+    async fn reset_prompt(&mut self, settings: &SessionSettings) -> Result<()>;
     async fn send(&mut self, data: &[u8]) -> Result<()>;
     async fn disconnect(&mut self) -> Result<()>;
 
+    /// The remote host the session is connected to.
+    fn host(&self) -> &str;
+    /// The full remote endpoint, e.g. `user@host:port`.
+    fn remote(&self) -> String;
+    fn state(&self) -> SessionState;
+    fn stats(&self) -> SessionStats;
     fn name(&self) -> Option<&str>;
     fn name_mut(&mut self) -> &mut String;
+    fn tags(&self) -> &Tags;
+    fn tags_mut(&mut self) -> &mut Tags;
+    /// The session's log of input and output.
+    fn transcript(&self) -> &Transcript;
+    /// The session's asciicast recording, if one is being made.
+    fn recording(&self) -> &Recording;
+    /// Responses to the session's output.
+    fn triggers(&self) -> &Triggers;
+    /// The shell prompt: the configured one, or else the one learned from the session.
+    fn prompt(&self, settings: &SessionSettings) -> Option<PromptPattern>;
+
+    /// Subscribe to the session's recent output.
+    fn output(&self) -> watch::Receiver<OutputBuffer>;
+    /// Subscribe to the session's exit status, which is set once the remote end goes away.
+    fn exit(&self) -> watch::Receiver<Option<ExitStatus>>;
 }
 
 impl<'a> Context<'a> {
-    pub async fn start_session<S: Session + 'a>(&mut self, mut session: S) -> Result<()> {
+    /// Connect a new session, resuming it if `foreground` is set, and return its index.
+    pub async fn start_session<S: Session + 'a>(
+        &mut self,
+        mut session: S,
+        foreground: bool,
+    ) -> Result<usize> {
         let session_index = self.sessions.next_index();
         session.connect().await?;
 
         self.sessions.push(Box::new(session));
+        let handle = self.sessions.handle(session_index).unwrap();
+        let session = self.sessions.get(session_index).unwrap();
+        session.triggers().attach(handle, self.tx_fired.clone());
+        session.triggers().load(&session.remote());
+        if self.settings.log.enabled {
+            // A missing transcript isn't worth refusing the session over.
+            if let Err(e) = self.set_logging(session_index, true) {
+                eprintln_colored(e, Color::Red)?;
+            }
+        }
+        if foreground {
+            self.handle_session(session_index).await?;
+        }
+
+        Ok(session_index)
+    }
+
+    pub async fn resume_session(&mut self, session_selection: SessionSelection) -> Result<()> {
+        let session_index = self.select_session(&session_selection)?;
+        if self.sessions.get(session_index).unwrap().state() == SessionState::Closed {
+            bail!("Session {session_selection} is closed, use `session revive {session_selection}` to reconnect.");
+        }
         self.handle_session(session_index).await?;
 
         Ok(())
     }
 
-    pub async fn resume_session(&mut self, session_selection: SessionSelection) -> Result<()> {
-        let session_index = match session_selection {
-            SessionSelection::Index(index) => index,
-            SessionSelection::Name(name) => {
-                let Some(session_index) = self
+    /// Find the index of the selected session, which must be the only one selected.
+    pub fn select_session(&self, session_selection: &SessionSelection) -> Result<usize> {
+        match self.select_sessions(session_selection)?[..] {
+            [index] => Ok(index),
+            ref indices => bail!(
+                "{session_selection} matches {} sessions, select a single one.",
+                indices.len()
+            ),
+        }
+    }
+
+    /// Find the indices of all selected sessions.
+    pub fn select_sessions(&self, session_selection: &SessionSelection) -> Result<Vec<usize>> {
+        let handle = match session_selection {
+            SessionSelection::Index(index) => {
+                if self.sessions.get(*index).is_none() {
+                    bail!("No session found with index {index}.");
+                }
+                return Ok(vec![*index]);
+            }
+            SessionSelection::Handle(handle) => *handle,
+            SessionSelection::Name(name) => match self.named_sessions.get(name) {
+                Some(handle) => *handle,
+                None => bail!("No session found with name {name}."),
+            },
+            SessionSelection::Tag(filter) => {
+                let indices: Vec<_> = self
                     .sessions
                     .iter()
-                    .flatten()
-                    .position(|session| session.name() == Some(&name))
-                else {
-                    bail!("No session found with name {name}.");
-                };
-                session_index
+                    .enumerate()
+                    .filter(|(_, session)| {
+                        session
+                            .as_ref()
+                            .is_some_and(|session| session.tags().matches(filter))
+                    })
+                    .map(|(index, _)| index)
+                    .collect();
+                if indices.is_empty() {
+                    bail!("No sessions found with tag {filter}.");
+                }
+                return Ok(indices);
             }
         };
 
-        self.handle_session(session_index).await?;
+        match self.sessions.resolve(handle) {
+            Some(index) => Ok(vec![index]),
+            None => bail!("Session {session_selection} no longer exists."),
+        }
+    }
+
+    /// Reconnect a closed session and resume it.
+    pub async fn revive_session(&mut self, session_index: usize) -> Result<()> {
+        let Some(session) = self.sessions.get_mut(session_index) else {
+            bail!("Could not find session with index {session_index}.");
+        };
+        if session.state() == SessionState::Connected {
+            bail!("Session {session_index} is still connected.");
+        }
+        session.disconnect().await?;
+        session.connect().await?;
+        self.handle_session(session_index).await
+    }
+
+    /// Disconnect a session, keeping it around as closed.
+    pub async fn close_session(&mut self, session_index: usize) -> Result<()> {
+        let Some(session) = self.sessions.get_mut(session_index) else {
+            bail!("Could not find session with index {session_index}.");
+        };
+        let res = session.disconnect().await;
+        let exit = session.exit().borrow().clone();
+        if let Some(exit) = exit {
+            let label = self.session_label(session_index);
+            println(format!("Session {label} closed: {exit}"))?;
+        }
+        res
+    }
+
+    /// Forget about a session that is no longer connected.
+    pub fn purge_session(&mut self, session_index: usize) -> Result<()> {
+        let Some(session) = self.sessions.get(session_index) else {
+            bail!("Could not find session with index {session_index}.");
+        };
+        if session.state() == SessionState::Connected {
+            bail!("Session {session_index} is still connected, use `session kill` first.");
+        }
+        let name = session.name().map(str::to_owned);
 
+        self.sessions.remove(session_index);
+        if let Some(name) = name {
+            self.named_sessions.remove(&name);
+        }
+
+        Ok(())
+    }
+
+    /// Change the name of a session, keeping names unique.
+    pub fn rename_session(&mut self, session_index: usize, name: String) -> Result<()> {
+        let Some(handle) = self.sessions.handle(session_index) else {
+            bail!("No session found with index {session_index}.");
+        };
+        if !matches!(name.parse(), Ok(SessionSelection::Name(_))) {
+            bail!("Session names can't look like an index, handle, tag or group.");
+        }
+        if self
+            .named_sessions
+            .get(&name)
+            .is_some_and(|other| *other != handle)
+        {
+            bail!("Another session is already named {name}.");
+        }
+
+        let session = self.sessions.get_mut(session_index).unwrap();
+        let old_name = std::mem::replace(session.name_mut(), name.clone());
+        self.named_sessions.remove(&old_name);
+        self.named_sessions.insert(name, handle);
         Ok(())
     }
 
-    async fn handle_session(&mut self, session_index: usize) -> Result<()> {
+    /// Find the session a switch hotkey refers to, skipping closed sessions.
+    fn switch_target(&self, session_index: usize, target: SwitchTarget) -> Option<usize> {
+        let open: Vec<_> = self
+            .sessions
+            .iter()
+            .enumerate()
+            .filter(|(_, session)| {
+                session
+                    .as_ref()
+                    .is_some_and(|session| session.state() != SessionState::Closed)
+            })
+            .map(|(index, _)| index)
+            .collect();
+        let position = open.iter().position(|index| *index == session_index);
+
+        let index = match (target, position) {
+            (SwitchTarget::Index(index), _) => index,
+            (SwitchTarget::Next, Some(position)) => open[(position + 1) % open.len()],
+            (SwitchTarget::Previous, Some(position)) => {
+                open[(position + open.len() - 1) % open.len()]
+            }
+            (SwitchTarget::Last, _) => self.sessions.resolve(self.last_session?)?,
+            (_, None) => return None,
+        };
+        (index != session_index && open.contains(&index)).then_some(index)
+    }
+
+    /// Carry out the action of a trigger.
+    pub(crate) async fn run_trigger(&mut self, fired: FiredTrigger) -> Result<()> {
+        let Some(fired) = self.answer_trigger(fired).await? else {
+            return Ok(());
+        };
+        // The session may have been purged since it fired.
+        let Some(session_index) = self.sessions.resolve(fired.handle) else {
+            return Ok(());
+        };
+        let label = self.session_label(session_index);
+        match fired.action {
+            TriggerAction::Send { .. } => {}
+            TriggerAction::Bell => {
+                println_colored(
+                    format!("\x07[{label}] triggered by {}", fired.matched),
+                    Color::DarkYellow,
+                )?;
+            }
+            TriggerAction::Command(command) => {
+                println_colored(
+                    format!("[{label}] triggered `{command}`"),
+                    Color::DarkYellow,
+                )?;
+                if let Err(e) = self.run_line(&command).await {
+                    eprintln_colored(e, Color::Red)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Send the response of a trigger that sends one, giving back any other trigger. Unlike other
+    /// actions, this can be done while waiting on a session, e.g. in `expect`.
+    pub(crate) async fn answer_trigger(
+        &mut self,
+        fired: FiredTrigger,
+    ) -> Result<Option<FiredTrigger>> {
+        let TriggerAction::Send { text, newline } = &fired.action else {
+            return Ok(Some(fired));
+        };
+        let Some(session_index) = self.sessions.resolve(fired.handle) else {
+            return Ok(None);
+        };
+        if let Err(e) = self.send_text(session_index, text, *newline).await {
+            let label = self.session_label(session_index);
+            eprintln_colored(
+                format!("[{label}] Failed to answer a trigger: {e}"),
+                Color::Red,
+            )?;
+        }
+        Ok(None)
+    }
+
+    async fn handle_session(&mut self, mut session_index: usize) -> Result<()> {
+        let settings = self.settings.session.clone();
         loop {
             let res = {
                 let Some(session) = self.sessions.get_mut(session_index) else {
                     bail!("No session found with index {session_index}.");
                 };
-                session.start_read_loop().await?
+                session
+                    .start_read_loop(&settings, &mut self.rx_fired)
+                    .await?
             };
 
             println("")?;
@@ -85,8 +441,8 @@ impl<'a> Context<'a> {
                             let Some(session) = self.sessions.get_mut(session_index) else {
                                 bail!("Could not find session with index {session_index}.");
                             };
-                            session.reset_prompt().await?;
-                            session.send(format!("{cmd}\n").as_bytes()).await?;
+                            session.reset_prompt(&settings).await?;
+                            session.send(&[cmd.as_slice(), b"\n"].concat()).await?;
                             continue;
                         }
                         TermcraftResponse::Background => break,
@@ -94,31 +450,63 @@ impl<'a> Context<'a> {
                             let Some(session) = self.sessions.get_mut(session_index) else {
                                 bail!("Could not find session with index {session_index}.");
                             };
-                            session.reset_prompt().await?;
+                            session.reset_prompt(&settings).await?;
                             continue;
                         }
                     }
                 }
                 SessionExit::Exit => {
-                    let name = {
-                        let Some(session) = self.sessions.get_mut(session_index) else {
-                            bail!("Could not find session with index {session_index}.");
-                        };
-                        session.disconnect().await?;
-                        session.name().map(|str| str.to_string())
-                    };
-
-                    self.sessions.remove(session_index);
-
-                    if let Some(name) = name {
-                        self.named_sessions.remove(&name);
-                    }
-
+                    self.close_session(session_index).await?;
                     break;
                 }
+                SessionExit::Switch(target) => {
+                    if let Some(index) = self.switch_target(session_index, target) {
+                        self.last_session = self.sessions.handle(session_index);
+                        session_index = index;
+                        println(format!("[{}]", self.session_label(session_index)))?;
+                    }
+                }
+                SessionExit::Trigger(fired) => self.run_trigger(fired).await?,
             }
         }
 
+        self.last_session = self.sessions.handle(session_index);
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_selection() {
+        assert_eq!(
+            "2".parse::<SessionSelection>().unwrap(),
+            SessionSelection::Index(2)
+        );
+        assert_eq!(
+            "2.1".parse::<SessionSelection>().unwrap(),
+            SessionSelection::Handle(Handle {
+                index: 2,
+                generation: 1
+            })
+        );
+        assert_eq!(
+            "web".parse::<SessionSelection>().unwrap(),
+            SessionSelection::Name("web".to_string())
+        );
+        assert_eq!(
+            "box=web01".parse::<SessionSelection>().unwrap(),
+            SessionSelection::Tag("box=web01".parse().unwrap())
+        );
+        assert_eq!(
+            "@blue".parse::<SessionSelection>().unwrap(),
+            SessionSelection::Tag(TagFilter::group("blue".to_string()))
+        );
+        assert_eq!(
+            "@".parse::<SessionSelection>().unwrap(),
+            SessionSelection::Name("@".to_string())
+        );
+    }
+}
@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use futures::future::{pending, select_all, FutureExt};
+use regex::Regex;
+use tokio::{select, sync::watch};
+
+use super::{triggers::PENDING_LIMIT, ExitStatus, Handle, OutputBuffer, SessionManager};
+use crate::{settings::NotificationSettings, terminal::strip_escapes};
+
+/// Watches sessions that aren't in the foreground and reports anything worth knowing about, like
+/// a shell's job notifications.
+#[derive(Debug, Default)]
+pub struct Notifier {
+    /// Announce when a background session prints something.
+    output: bool,
+    /// Announce when a background session exits.
+    exit: bool,
+    /// Announce lines of background output matching any of these.
+    patterns: Vec<Regex>,
+    watched: HashMap<Handle, Watched>,
+}
+
+#[derive(Debug)]
+struct Watched {
+    label: String,
+    rx_output: watch::Receiver<OutputBuffer>,
+    rx_exit: watch::Receiver<Option<ExitStatus>>,
+    /// How much output has already been checked.
+    seen: usize,
+    /// The unfinished last line of output, which may match once the rest of it arrives.
+    line: String,
+    /// New output has been announced since the session was last in the foreground.
+    announced_output: bool,
+    announced_exit: bool,
+    foreground: bool,
+    /// The session has been dropped, so there is nothing more to watch.
+    closed: bool,
+}
+
+impl Notifier {
+    pub fn new(settings: &NotificationSettings) -> Result<Self> {
+        Ok(Self {
+            output: settings.output,
+            exit: settings.exit,
+            patterns: settings
+                .patterns
+                .iter()
+                .map(|pattern| Regex::new(pattern))
+                .collect::<Result<_, _>>()?,
+            watched: HashMap::new(),
+        })
+    }
+
+    /// Start watching new sessions and stop watching removed ones. Output of the `foreground`
+    /// session counts as seen.
+    pub fn sync(
+        &mut self,
+        sessions: &SessionManager,
+        labels: &[String],
+        foreground: Option<usize>,
+    ) {
+        let mut watched = HashMap::new();
+        for (index, session) in sessions.iter().enumerate() {
+            let (Some(session), Some(handle)) = (session, sessions.handle(index)) else {
+                continue;
+            };
+
+            let mut entry = self.watched.remove(&handle).unwrap_or_else(|| {
+                let rx_output = session.output();
+                let rx_exit = session.exit();
+                let seen = rx_output.borrow().written();
+                let announced_exit = rx_exit.borrow().is_some();
+                Watched {
+                    label: String::new(),
+                    seen,
+                    line: String::new(),
+                    announced_output: false,
+                    announced_exit,
+                    rx_output,
+                    rx_exit,
+                    foreground: false,
+                    closed: false,
+                }
+            });
+            entry.label = labels[index].clone();
+            // Exits of the foreground session are seen as they happen, and a revived session may
+            // exit again.
+            let exited = entry.rx_exit.borrow().is_some();
+            entry.announced_exit = (entry.announced_exit || entry.foreground) && exited;
+            entry.foreground = foreground == Some(index);
+            if entry.foreground {
+                entry.seen = entry.rx_output.borrow_and_update().written();
+                entry.line.clear();
+                entry.announced_output = false;
+            }
+            watched.insert(handle, entry);
+        }
+        self.watched = watched;
+    }
+
+    /// Wait until a background session has something to announce.
+    pub async fn next(&mut self) -> Vec<String> {
+        loop {
+            let changes: Vec<_> = self
+                .watched
+                .values_mut()
+                .filter(|watched| !watched.foreground && !watched.closed)
+                .map(|watched| {
+                    async {
+                        let changed = select! {
+                            res = watched.rx_output.changed() => res.is_ok(),
+                            res = watched.rx_exit.changed() => res.is_ok(),
+                        };
+                        if !changed {
+                            watched.closed = true;
+                        }
+                    }
+                    .boxed()
+                })
+                .collect();
+            if changes.is_empty() {
+                pending::<()>().await;
+            }
+            select_all(changes).await;
+
+            let notices = self.notices();
+            if !notices.is_empty() {
+                return notices;
+            }
+        }
+    }
+
+    fn notices(&mut self) -> Vec<String> {
+        let mut notices = Vec::new();
+
+        for watched in self.watched.values_mut() {
+            if watched.foreground || watched.closed {
+                continue;
+            }
+
+            let output = {
+                let buffer = watched.rx_output.borrow_and_update();
+                let output = buffer.since(watched.seen);
+                watched.seen = buffer.written();
+                output
+            };
+            let text = String::from_utf8_lossy(&strip_escapes(&output)).into_owned();
+            watched.line.push_str(&text);
+            let matches = matching_lines(&self.patterns, &mut watched.line);
+            let matched = !matches.is_empty();
+            for (pattern, line) in matches {
+                notices.push(format!("[{}] matched {pattern}: {line}", watched.label));
+            }
+            if self.output && !matched && !output.is_empty() && !watched.announced_output {
+                notices.push(format!("[{}] has new output", watched.label));
+                watched.announced_output = true;
+            }
+
+            if let Some(exit) = &*watched.rx_exit.borrow_and_update() {
+                if self.exit && !watched.announced_exit {
+                    notices.push(format!("[{}] {exit}", watched.label));
+                }
+                watched.announced_exit = true;
+            }
+        }
+
+        notices
+    }
+}
+
+/// Find the lines of `pending` matching any of `patterns`, along with the pattern, and leave only
+/// an unfinished last line that hasn't matched yet.
+fn matching_lines(patterns: &[Regex], pending: &mut String) -> Vec<(String, String)> {
+    let mut matches = Vec::new();
+    let mut last_matched = false;
+    for line in pending.split('\n') {
+        last_matched = false;
+        if let Some(pattern) = patterns.iter().find(|pattern| pattern.is_match(line)) {
+            matches.push((pattern.to_string(), line.trim().to_owned()));
+            last_matched = true;
+        }
+    }
+
+    if last_matched {
+        pending.clear();
+    } else if let Some(newline) = pending.rfind('\n') {
+        pending.drain(..=newline);
+    }
+    if let Some(excess) = pending.len().checked_sub(PENDING_LIMIT) {
+        let start = (excess..pending.len())
+            .find(|start| pending.is_char_boundary(*start))
+            .unwrap_or(pending.len());
+        pending.drain(..start);
+    }
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::output::OUTPUT_BUFFER_SIZE;
+
+    fn watched(
+        rx_output: watch::Receiver<OutputBuffer>,
+        rx_exit: watch::Receiver<Option<ExitStatus>>,
+    ) -> Watched {
+        Watched {
+            label: "web".to_string(),
+            rx_output,
+            rx_exit,
+            seen: 0,
+            line: String::new(),
+            announced_output: false,
+            announced_exit: false,
+            foreground: false,
+            closed: false,
+        }
+    }
+
+    #[test]
+    fn notices() {
+        let (tx_output, rx_output) = watch::channel(OutputBuffer::default());
+        let (tx_exit, rx_exit) = watch::channel(None);
+        let mut notifier = Notifier::new(&NotificationSettings {
+            patterns: vec![r"flag\{\w+\}".to_string()],
+            ..Default::default()
+        })
+        .unwrap();
+        let handle = Handle {
+            index: 0,
+            generation: 0,
+        };
+        notifier.watched.insert(handle, watched(rx_output, rx_exit));
+
+        tx_output.send_modify(|output| output.push(b"$ ls\r\n"));
+        assert_eq!(notifier.notices(), ["[web] has new output"]);
+        tx_output.send_modify(|output| output.push(b"$ ls -a\r\n"));
+        assert!(notifier.notices().is_empty());
+
+        tx_output.send_modify(|output| output.push(b"\x1b[1mflag{abc}\x1b[0m\r\n"));
+        assert_eq!(
+            notifier.notices(),
+            [r"[web] matched flag\{\w+\}: flag{abc}"]
+        );
+
+        // Lines can arrive in pieces.
+        tx_output.send_modify(|output| output.push(b"$ cat flag\r\nfla"));
+        assert!(notifier.notices().is_empty());
+        tx_output.send_modify(|output| output.push(b"g{def}\r\n$ "));
+        assert_eq!(
+            notifier.notices(),
+            [r"[web] matched flag\{\w+\}: flag{def}"]
+        );
+        tx_output.send_modify(|output| output.push(b"\r\n"));
+        assert!(notifier.notices().is_empty());
+
+        // Output that no longer fits in the buffer is still only checked once.
+        tx_output.send_modify(|output| {
+            output.push(b"flag{ghi}\r\n");
+            output.push(&vec![b'.'; OUTPUT_BUFFER_SIZE]);
+            output.push(b"\r\nflag{jkl}\r\n");
+        });
+        assert_eq!(
+            notifier.notices(),
+            [r"[web] matched flag\{\w+\}: flag{jkl}"]
+        );
+        assert!(notifier.notices().is_empty());
+        tx_output.send_modify(|output| output.push(b"$ "));
+        assert!(notifier.notices().is_empty());
+
+        tx_exit.send_replace(Some(ExitStatus::Code(1)));
+        assert_eq!(notifier.notices(), ["[web] Process exited with code 1."]);
+        assert!(notifier.notices().is_empty());
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Local};
+
+use crate::terminal::strip_escapes;
+
+/// Maximum number of bytes of output retained per session.
+pub const OUTPUT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The most recent output of a session.
+///
+/// Consumers keep track of how far they have read using [`OutputBuffer::written`], so that
+/// printing, searching, etc. can each read the same output independently.
+#[derive(Debug, Clone, Default)]
+pub struct OutputBuffer {
+    data: VecDeque<u8>,
+    written: usize,
+    updated: Option<DateTime<Local>>,
+}
+
+impl OutputBuffer {
+    pub fn push(&mut self, data: &[u8]) {
+        self.data.extend(data);
+        self.written += data.len();
+        self.updated = Some(Local::now());
+
+        let excess = self.data.len().saturating_sub(OUTPUT_BUFFER_SIZE);
+        self.data.drain(..excess);
+    }
+
+    /// Total number of bytes ever written, including those no longer retained.
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    /// When output was last received.
+    pub fn updated(&self) -> Option<DateTime<Local>> {
+        self.updated
+    }
+
+    /// Retained bytes written after `position`.
+    pub fn since(&self, position: usize) -> Vec<u8> {
+        let start = self.written - self.data.len();
+        let skip = position.saturating_sub(start).min(self.data.len());
+        self.data.range(skip..).copied().collect()
+    }
+
+    pub fn contents(&self) -> Vec<u8> {
+        self.data.iter().copied().collect()
+    }
+
+    /// Retained output as text, with terminal escape sequences and carriage returns removed.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&strip_escapes(&self.contents())).into_owned()
+    }
+
+    /// The last complete line of output, ignoring a trailing partial line such as a shell prompt.
+    pub fn last_line(&self) -> Option<String> {
+        let text = self.text();
+        let complete = &text[..text.rfind('\n')?];
+        complete
+            .lines()
+            .rev()
+            .find(|line| !line.trim().is_empty())
+            .map(str::to_owned)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn since() {
+        let mut buffer = OutputBuffer::default();
+        buffer.push(b"hello ");
+        let position = buffer.written();
+        buffer.push(b"world");
+
+        assert_eq!(buffer.since(0), b"hello world");
+        assert_eq!(buffer.since(position), b"world");
+        assert_eq!(buffer.since(buffer.written()), b"");
+    }
+
+    #[test]
+    fn truncates() {
+        let mut buffer = OutputBuffer::default();
+        buffer.push(&[b'a'; OUTPUT_BUFFER_SIZE]);
+        buffer.push(b"bc");
+
+        assert_eq!(buffer.written(), OUTPUT_BUFFER_SIZE + 2);
+        assert_eq!(buffer.contents().len(), OUTPUT_BUFFER_SIZE);
+        assert_eq!(buffer.since(1), buffer.contents());
+        assert_eq!(buffer.since(OUTPUT_BUFFER_SIZE + 1), b"c");
+    }
+
+    #[test]
+    fn text_and_last_line() {
+        let mut buffer = OutputBuffer::default();
+        buffer.push(b"\x1b[01;32mputs@ 0x7ffff7a62aa0\x1b[0m\r\n\r\n\x1b]0;title\x07user@box:~$ ");
+
+        assert_eq!(buffer.text(), "puts@ 0x7ffff7a62aa0\n\nuser@box:~$ ");
+        assert_eq!(buffer.last_line().as_deref(), Some("puts@ 0x7ffff7a62aa0"));
+    }
+}
@@ -0,0 +1,107 @@
+use std::{fmt::Display, time::Duration};
+
+use regex::Regex;
+use tokio::{
+    sync::watch,
+    time::{timeout_at, Instant},
+};
+
+use super::OutputBuffer;
+use crate::terminal::strip_escapes;
+
+/// How long output has to stop for to be taken as a prompt when learning it.
+const QUIET: Duration = Duration::from_millis(200);
+
+/// How to recognise a session's shell prompt at the end of its output.
+#[derive(Debug, Clone)]
+pub enum PromptPattern {
+    /// A regex for the last line, from the settings.
+    Regex(Regex),
+    /// The end of a prompt seen before, e.g. `$ `.
+    Suffix(String),
+}
+
+impl PromptPattern {
+    /// Learn from a prompt seen in the output. Only its last symbol and any spaces after it are
+    /// kept, so that it still matches when e.g. the current directory in it changes.
+    pub fn learn(prompt: &str) -> Option<Self> {
+        let (start, symbol) = prompt.trim_end().char_indices().last()?;
+        if symbol.is_alphanumeric() {
+            return None;
+        }
+        Some(Self::Suffix(prompt[start..].to_owned()))
+    }
+
+    /// Whether the output, with escapes removed, ends in a prompt.
+    pub fn matches(&self, output: &str) -> bool {
+        let line = output.rsplit('\n').next().unwrap_or_default();
+        match self {
+            PromptPattern::Regex(regex) => regex.is_match(line),
+            PromptPattern::Suffix(suffix) => line.ends_with(suffix.as_str()),
+        }
+    }
+}
+
+/// Wait for the output after `start` to end in a prompt, returning the pattern that matched, or
+/// `None` on timeout. Without a pattern, the prompt is learned from the output once it goes quiet.
+pub async fn wait_for_prompt(
+    rx_output: &mut watch::Receiver<OutputBuffer>,
+    start: usize,
+    pattern: Option<&PromptPattern>,
+    timeout: Duration,
+) -> Option<PromptPattern> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let text = {
+            let output = rx_output.borrow_and_update();
+            String::from_utf8_lossy(&strip_escapes(&output.since(start))).into_owned()
+        };
+        let wait = match pattern {
+            Some(pattern) if pattern.matches(&text) => return Some(pattern.clone()),
+            Some(_) => deadline,
+            None => (Instant::now() + QUIET).min(deadline),
+        };
+
+        match timeout_at(wait, rx_output.changed()).await {
+            Ok(Ok(())) => continue,
+            Ok(Err(_)) => return None,
+            // A shell prints its prompt last, then waits for input.
+            Err(_) if pattern.is_none() => {
+                return PromptPattern::learn(text.rsplit('\n').next().unwrap_or_default())
+            }
+            Err(_) => return None,
+        }
+    }
+}
+
+impl Display for PromptPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PromptPattern::Regex(regex) => write!(f, "{regex}"),
+            PromptPattern::Suffix(suffix) => write!(f, "{suffix:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn learned_prompts() {
+        let prompt = PromptPattern::learn("user@box:~$ ").unwrap();
+        assert!(prompt.matches("^C\nuser@box:/tmp$ "));
+        assert!(!prompt.matches("user@box:/tmp$ ls\nfile"));
+        assert!(!prompt.matches("$ \n"));
+
+        assert!(PromptPattern::learn("root@box:~# ")
+            .unwrap()
+            .matches("root@web:/# "));
+        assert!(PromptPattern::learn("Loading").is_none());
+        assert!(PromptPattern::learn("  ").is_none());
+
+        let prompt = PromptPattern::Regex(Regex::new(r"^\(gdb\) $").unwrap());
+        assert!(prompt.matches("Continuing.\n(gdb) "));
+        assert!(!prompt.matches("(gdb) run"));
+    }
+}
@@ -0,0 +1,134 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use anyhow::Result;
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+
+/// A recording of a session's output in asciicast v2 format, which can be played back with
+/// `replay` or asciinema.
+#[derive(Debug, Clone, Default)]
+pub struct Recording(Arc<Mutex<Option<RecordingFile>>>);
+
+#[derive(Debug)]
+struct RecordingFile {
+    path: PathBuf,
+    file: BufWriter<File>,
+    started: Instant,
+    /// The start of a UTF-8 character split across chunks of output.
+    pending: Vec<u8>,
+}
+
+/// The first line of an asciicast v2 file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CastHeader {
+    pub version: u32,
+    pub width: u16,
+    pub height: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+/// Every other line: seconds since the start, the event type (`o` for output) and its data.
+pub type CastEvent = (f64, String, String);
+
+impl Recording {
+    /// Start a new recording at `path` for a terminal of the given size.
+    pub fn start(&self, path: &Path, (width, height): (u16, u16), title: &str) -> Result<()> {
+        let mut recording = self.0.lock().unwrap();
+        if recording.is_some() {
+            return Ok(());
+        }
+
+        let header = CastHeader {
+            version: 2,
+            width,
+            height,
+            timestamp: Some(Local::now().timestamp()),
+            title: Some(title.to_owned()),
+        };
+        let mut file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut file, &header)?;
+        writeln!(file)?;
+        file.flush()?;
+
+        *recording = Some(RecordingFile {
+            path: path.to_owned(),
+            file,
+            started: Instant::now(),
+            pending: Vec::new(),
+        });
+        Ok(())
+    }
+
+    /// Stop recording, returning where the recording was written.
+    pub fn stop(&self) -> Option<PathBuf> {
+        let mut recording = self.0.lock().unwrap().take()?;
+        recording.file.flush().ok();
+        Some(recording.path)
+    }
+
+    pub fn path(&self) -> Option<PathBuf> {
+        Some(self.0.lock().unwrap().as_ref()?.path.clone())
+    }
+
+    pub fn output(&self, data: &[u8]) {
+        let mut recording = self.0.lock().unwrap();
+        let Some(recording) = &mut *recording else {
+            return;
+        };
+
+        recording.pending.extend_from_slice(data);
+        let text = take_utf8(&mut recording.pending);
+        if text.is_empty() {
+            return;
+        }
+        let event: CastEvent = (
+            recording.started.elapsed().as_secs_f64(),
+            "o".to_owned(),
+            text,
+        );
+        // A recording is not worth interrupting the session over.
+        serde_json::to_writer(&mut recording.file, &event).ok();
+        writeln!(recording.file).ok();
+        recording.file.flush().ok();
+    }
+}
+
+/// Take as much of `data` as is valid UTF-8, leaving an incomplete character at the end for the
+/// next chunk. Invalid bytes are replaced.
+fn take_utf8(data: &mut Vec<u8>) -> String {
+    let end = match std::str::from_utf8(data) {
+        Ok(_) => data.len(),
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        Err(_) => data.len(),
+    };
+    let text = String::from_utf8_lossy(&data[..end]).into_owned();
+    data.drain(..end);
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_characters() {
+        let mut data = "é".as_bytes()[..1].to_vec();
+        assert_eq!(take_utf8(&mut data), "");
+        data.extend_from_slice(&"é".as_bytes()[1..]);
+        data.extend_from_slice(b"!");
+        assert_eq!(take_utf8(&mut data), "é!");
+        assert!(data.is_empty());
+
+        let mut data = b"a\xffb".to_vec();
+        assert_eq!(take_utf8(&mut data), "a\u{fffd}b");
+    }
+}
@@ -1,8 +1,19 @@
-use std::{cmp::Reverse, collections::BinaryHeap, fmt::Debug};
+use std::{cmp::Reverse, collections::BinaryHeap, fmt::Debug, fmt::Display, str::FromStr};
+
+use anyhow::{anyhow, Error, Result};
+
+/// Identifies one item for as long as it exists. Indices are reused after a removal, but the
+/// generation is bumped so old handles no longer match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Handle {
+    pub index: usize,
+    pub generation: usize,
+}
 
 #[derive(Debug, Clone)]
 pub struct StableVec<T> {
     items: Vec<Option<T>>,
+    generations: Vec<usize>,
     available_indices: BinaryHeap<Reverse<usize>>,
 }
 
@@ -11,6 +22,7 @@ impl<T> StableVec<T> {
     pub fn new() -> Self {
         Self {
             items: Vec::new(),
+            generations: Vec::new(),
             available_indices: BinaryHeap::new(),
         }
     }
@@ -34,23 +46,22 @@ impl<T> StableVec<T> {
         } else {
             let index = self.items.len();
             self.items.push(Some(item));
+            self.generations.push(0);
             index
         }
     }
 
     pub fn get(&self, index: usize) -> Option<&T> {
-        self.items.get(index).map(|maybe| maybe.as_ref()).flatten()
+        self.items.get(index).and_then(|maybe| maybe.as_ref())
     }
 
     pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
-        self.items
-            .get_mut(index)
-            .map(|maybe| maybe.as_mut())
-            .flatten()
+        self.items.get_mut(index).and_then(|maybe| maybe.as_mut())
     }
 
     pub fn remove(&mut self, index: usize) -> Option<T> {
-        if let Some(item) = self.items.get_mut(index).map(|elem| elem.take()).flatten() {
+        if let Some(item) = self.items.get_mut(index).and_then(|elem| elem.take()) {
+            self.generations[index] += 1;
             self.available_indices.push(Reverse(index));
             Some(item)
         } else {
@@ -58,6 +69,20 @@ impl<T> StableVec<T> {
         }
     }
 
+    /// The handle of the item currently at `index`.
+    pub fn handle(&self, index: usize) -> Option<Handle> {
+        self.get(index)?;
+        Some(Handle {
+            index,
+            generation: self.generations[index],
+        })
+    }
+
+    /// The index of the item `handle` refers to, if it hasn't been removed.
+    pub fn resolve(&self, handle: Handle) -> Option<usize> {
+        (self.handle(handle.index)? == handle).then_some(handle.index)
+    }
+
     pub fn iter(&self) -> std::slice::Iter<'_, Option<T>> {
         self.items.iter()
     }
@@ -72,13 +97,36 @@ where
     Collection: IntoIterator<Item = T>,
 {
     fn from(items: Collection) -> Self {
+        let items: Vec<_> = items.into_iter().map(|item| Some(item)).collect();
         Self {
-            items: items.into_iter().map(|item| Some(item)).collect(),
+            generations: vec![0; items.len()],
+            items,
             available_indices: BinaryHeap::new(),
         }
     }
 }
 
+impl Display for Handle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.index, self.generation)
+    }
+}
+
+impl FromStr for Handle {
+    type Err = Error;
+
+    /// Parse a handle written as `index.generation`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (index, generation) = s
+            .split_once('.')
+            .ok_or_else(|| anyhow!("Expected a handle like 1.0, found {s}."))?;
+        Ok(Self {
+            index: index.parse()?,
+            generation: generation.parse()?,
+        })
+    }
+}
+
 impl<T: PartialEq> PartialEq for StableVec<T> {
     fn eq(&self, other: &Self) -> bool {
         self.items == other.items
@@ -157,4 +205,20 @@ mod tests {
         assert_eq!(stable_vec.get(2), Some(&Foo(6)));
         assert_eq!(stable_vec.get(3), Some(&Foo(4)));
     }
+
+    #[test]
+    fn stale_handles() {
+        let mut stable_vec = StableVec::from([Foo(1), Foo(2)]);
+        let handle = stable_vec.handle(1).unwrap();
+        assert_eq!(stable_vec.resolve(handle), Some(1));
+
+        stable_vec.remove(1);
+        assert_eq!(stable_vec.resolve(handle), None);
+
+        stable_vec.push(Foo(3));
+        assert_eq!(stable_vec.resolve(handle), None);
+        let new_handle = stable_vec.handle(1).unwrap();
+        assert_eq!(new_handle.to_string(), "1.1");
+        assert_eq!("1.1".parse::<Handle>().unwrap(), new_handle);
+    }
 }
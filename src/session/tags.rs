@@ -0,0 +1,118 @@
+use std::{collections::BTreeMap, fmt::Display, str::FromStr};
+
+use anyhow::{bail, Error, Result};
+
+/// Labels attached to a session, e.g. `box=web01` or `team=3`. A tag without a value, like `prod`,
+/// is stored with an empty value.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tags(BTreeMap<String, String>);
+
+/// Matches sessions by a tag, either `key=value` or just `key` to match any value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagFilter {
+    pub key: String,
+    pub value: Option<String>,
+}
+
+/// The tag that a group selector like `@blue` matches.
+const GROUP_TAG: &str = "group";
+
+impl Tags {
+    /// Add or replace a tag written as `key=value` or `key`.
+    pub fn set(&mut self, tag: &str) -> Result<()> {
+        let (key, value) = tag.split_once('=').unwrap_or((tag, ""));
+        if key.is_empty() {
+            bail!("Tags need a name, e.g. team=3.");
+        }
+        self.0.insert(key.to_owned(), value.to_owned());
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &str) -> bool {
+        self.0.remove(key).is_some()
+    }
+
+    pub fn matches(&self, filter: &TagFilter) -> bool {
+        match (self.0.get(&filter.key), &filter.value) {
+            (Some(value), Some(expected)) => value == expected,
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+}
+
+impl Display for Tags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, (key, value)) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            if value.is_empty() {
+                write!(f, "{key}")?;
+            } else {
+                write!(f, "{key}={value}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TagFilter {
+    pub fn group(group: String) -> Self {
+        Self {
+            key: GROUP_TAG.to_owned(),
+            value: Some(group),
+        }
+    }
+}
+
+impl FromStr for TagFilter {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (key, value) = match s.split_once('=') {
+            Some((key, value)) => (key, Some(value.to_owned())),
+            None => (s, None),
+        };
+        if key.is_empty() {
+            bail!("Tag filters need a name, e.g. team=3.");
+        }
+        Ok(Self {
+            key: key.to_owned(),
+            value,
+        })
+    }
+}
+
+impl Display for TagFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{}={value}", self.key),
+            None => write!(f, "{}", self.key),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags() {
+        let mut tags = Tags::default();
+        tags.set("box=web01").unwrap();
+        tags.set("prod").unwrap();
+        tags.set("team=3").unwrap();
+        tags.set("team=4").unwrap();
+        assert!(tags.set("=3").is_err());
+        assert_eq!(tags.to_string(), "box=web01 prod team=4");
+
+        assert!(tags.matches(&"team=4".parse().unwrap()));
+        assert!(!tags.matches(&"team=3".parse().unwrap()));
+        assert!(tags.matches(&"prod".parse().unwrap()));
+        assert!(!tags.matches(&TagFilter::group("blue".to_string())));
+
+        assert!(tags.remove("prod"));
+        assert!(!tags.matches(&"prod".parse().unwrap()));
+    }
+}
@@ -0,0 +1,114 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Local};
+
+/// A log of everything sent to and received from a session, shared between the session and
+/// whatever receives its output.
+///
+/// Each chunk of data is written on its own line with a timestamp and its direction, `>` for
+/// input and `<` for output, with non-printable bytes escaped so nothing is lost. Lines starting
+/// with `#` note when logging started and stopped.
+#[derive(Debug, Clone, Default)]
+pub struct Transcript(Arc<Mutex<TranscriptFile>>);
+
+#[derive(Debug, Default)]
+struct TranscriptFile {
+    /// Where the transcript is written, kept after logging stops so it can resume in the same
+    /// file.
+    path: Option<PathBuf>,
+    file: Option<File>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Input,
+    Output,
+}
+
+impl Transcript {
+    /// Start appending to the file at `path`, noting what is being logged.
+    pub fn start(&self, path: &Path, description: &str) -> Result<()> {
+        let mut transcript = self.0.lock().unwrap();
+        if transcript.file.is_some() {
+            return Ok(());
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "# {} started {description}", timestamp(Local::now()))?;
+        transcript.path = Some(path.to_owned());
+        transcript.file = Some(file);
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        let mut transcript = self.0.lock().unwrap();
+        if let Some(mut file) = transcript.file.take() {
+            writeln!(file, "# {} stopped", timestamp(Local::now())).ok();
+        }
+    }
+
+    pub fn is_logging(&self) -> bool {
+        self.0.lock().unwrap().file.is_some()
+    }
+
+    /// The file the transcript is, or was last, written to.
+    pub fn path(&self) -> Option<PathBuf> {
+        self.0.lock().unwrap().path.clone()
+    }
+
+    pub fn input(&self, data: &[u8]) {
+        self.record(Direction::Input, data);
+    }
+
+    pub fn output(&self, data: &[u8]) {
+        self.record(Direction::Output, data);
+    }
+
+    fn record(&self, direction: Direction, data: &[u8]) {
+        let mut transcript = self.0.lock().unwrap();
+        let Some(file) = &mut transcript.file else {
+            return;
+        };
+        // A transcript is not worth interrupting the session over.
+        file.write_all(entry(Local::now(), direction, data).as_bytes())
+            .ok();
+    }
+}
+
+fn timestamp(time: DateTime<Local>) -> String {
+    time.format("%Y-%m-%d %H:%M:%S%.3f").to_string()
+}
+
+fn entry(time: DateTime<Local>, direction: Direction, data: &[u8]) -> String {
+    let direction = match direction {
+        Direction::Input => '>',
+        Direction::Output => '<',
+    };
+    format!("{} {direction} {}\n", timestamp(time), data.escape_ascii())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn entries() {
+        let time = Local.with_ymd_and_hms(2024, 3, 1, 12, 30, 5).unwrap();
+        assert_eq!(
+            entry(time, Direction::Input, b"id\n"),
+            "2024-03-01 12:30:05.000 > id\\n\n"
+        );
+        assert_eq!(
+            entry(time, Direction::Output, b"\x1b[1mroot\x1b[0m\r\n\xff"),
+            "2024-03-01 12:30:05.000 < \\x1b[1mroot\\x1b[0m\\r\\n\\xff\n"
+        );
+    }
+}
@@ -0,0 +1,308 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{bail, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use super::Handle;
+use crate::{files::cache, terminal::strip_escapes};
+
+/// The most output kept while waiting for a line to finish or a trigger to match.
+pub(super) const PENDING_LIMIT: usize = 4096;
+/// Triggers of each remote, so that they are set up again when connecting to it later.
+const TRIGGERS_FILENAME: &str = "triggers.json";
+
+/// Responses to a session's output, checked as the output arrives whether or not the session is
+/// in the foreground.
+#[derive(Debug, Clone, Default)]
+pub struct Triggers(Arc<Mutex<TriggerSet>>);
+
+#[derive(Debug, Default)]
+struct TriggerSet {
+    triggers: Vec<Trigger>,
+    /// Output since the last match or newline, which a prompt may still be arriving in.
+    pending: String,
+    /// Where to report triggers that need rctf itself, along with the session's handle.
+    tx_fired: Option<(Handle, mpsc::UnboundedSender<FiredTrigger>)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "StoredTrigger", into = "StoredTrigger")]
+pub struct Trigger {
+    pub regex: Regex,
+    pub action: TriggerAction,
+}
+
+/// A trigger as stored on disk.
+#[derive(Serialize, Deserialize)]
+struct StoredTrigger {
+    regex: String,
+    action: TriggerAction,
+}
+
+impl TryFrom<StoredTrigger> for Trigger {
+    type Error = regex::Error;
+
+    fn try_from(trigger: StoredTrigger) -> Result<Self, Self::Error> {
+        Ok(Self {
+            regex: Regex::new(&trigger.regex)?,
+            action: trigger.action,
+        })
+    }
+}
+
+impl From<Trigger> for StoredTrigger {
+    fn from(trigger: Trigger) -> Self {
+        Self {
+            regex: trigger.regex.to_string(),
+            action: trigger.action,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TriggerAction {
+    /// Send text to the session. Variables in it are filled in whenever the trigger fires.
+    Send { text: String, newline: bool },
+    /// Run an rctf command, e.g. `var set shell 1`.
+    Command(String),
+    /// Ring the terminal bell and show the matching text.
+    Bell,
+}
+
+/// A trigger that has fired, for rctf to carry out its action.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FiredTrigger {
+    pub handle: Handle,
+    pub action: TriggerAction,
+    /// The text that matched.
+    pub matched: String,
+}
+
+impl Display for TriggerAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TriggerAction::Send {
+                text,
+                newline: true,
+            } => write!(f, "send {text}"),
+            TriggerAction::Send {
+                text,
+                newline: false,
+            } => write!(f, "send {text} (no newline)"),
+            TriggerAction::Command(command) => write!(f, "run {command}"),
+            TriggerAction::Bell => write!(f, "bell"),
+        }
+    }
+}
+
+impl Triggers {
+    /// Report fired triggers to rctf, as the session with this handle.
+    pub fn attach(&self, handle: Handle, tx_fired: mpsc::UnboundedSender<FiredTrigger>) {
+        self.0.lock().unwrap().tx_fired = Some((handle, tx_fired));
+    }
+
+    pub fn add(&self, trigger: Trigger) -> Result<()> {
+        // It would fire forever.
+        if trigger.regex.is_match("") {
+            bail!("Triggers can't match empty output.");
+        }
+        self.0.lock().unwrap().triggers.push(trigger);
+        Ok(())
+    }
+
+    pub fn remove(&self, index: usize) -> Result<Trigger> {
+        let mut set = self.0.lock().unwrap();
+        if index >= set.triggers.len() {
+            bail!("No trigger found with index {index}.");
+        }
+        Ok(set.triggers.remove(index))
+    }
+
+    pub fn clear(&self) {
+        self.0.lock().unwrap().triggers.clear();
+    }
+
+    pub fn list(&self) -> Vec<Trigger> {
+        self.0.lock().unwrap().triggers.clone()
+    }
+
+    /// Add the triggers saved for a remote, e.g. `root@10.0.0.5:22`.
+    pub fn load(&self, remote: &str) {
+        let mut saved: BTreeMap<String, Vec<Trigger>> =
+            cache::load(TRIGGERS_FILENAME).unwrap_or_default();
+        if let Some(triggers) = saved.remove(remote) {
+            self.0.lock().unwrap().triggers.extend(triggers);
+        }
+    }
+
+    /// Save the triggers for a remote, replacing those saved before.
+    pub fn save(&self, remote: &str) -> Result<()> {
+        let mut saved: BTreeMap<String, Vec<Trigger>> =
+            cache::load(TRIGGERS_FILENAME).unwrap_or_default();
+        let triggers = self.list();
+        if triggers.is_empty() {
+            saved.remove(remote);
+        } else {
+            saved.insert(remote.to_owned(), triggers);
+        }
+        cache::save(TRIGGERS_FILENAME, saved)
+    }
+
+    /// Check new output against the triggers, reporting those that fire to rctf.
+    pub fn feed(&self, data: &[u8]) {
+        let mut set = self.0.lock().unwrap();
+        if set.triggers.is_empty() {
+            return;
+        }
+
+        let text = String::from_utf8_lossy(&strip_escapes(data)).into_owned();
+        set.pending.push_str(&text);
+        for (action, matched) in set.fire() {
+            if let Some((handle, tx_fired)) = &set.tx_fired {
+                tx_fired
+                    .send(FiredTrigger {
+                        handle: *handle,
+                        action,
+                        matched,
+                    })
+                    .ok();
+            }
+        }
+    }
+}
+
+impl TriggerSet {
+    /// Find matches in the pending output, earliest first, consuming output up to the end of
+    /// each so that it only fires once. Empty matches, e.g. of `\b`, consume nothing, so they
+    /// never fire.
+    fn fire(&mut self) -> Vec<(TriggerAction, String)> {
+        let mut fired = Vec::new();
+        loop {
+            let earliest = self
+                .triggers
+                .iter()
+                .filter_map(|trigger| {
+                    let m = trigger
+                        .regex
+                        .find_iter(&self.pending)
+                        .find(|m| !m.is_empty())?;
+                    Some((trigger, m))
+                })
+                .min_by_key(|(_, m)| m.start());
+            let Some((trigger, m)) = earliest else {
+                break;
+            };
+            fired.push((trigger.action.clone(), m.as_str().to_owned()));
+            let end = m.end();
+            self.pending.drain(..end);
+        }
+        if let Some(newline) = self.pending.rfind('\n') {
+            self.pending.drain(..=newline);
+        }
+        if let Some(excess) = self.pending.len().checked_sub(PENDING_LIMIT) {
+            let start = (excess..self.pending.len())
+                .find(|start| self.pending.is_char_boundary(*start))
+                .unwrap_or(self.pending.len());
+            self.pending.drain(..start);
+        }
+        fired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trigger(regex: &str, action: TriggerAction) -> Trigger {
+        Trigger {
+            regex: Regex::new(regex).unwrap(),
+            action,
+        }
+    }
+
+    fn send(text: &str) -> TriggerAction {
+        TriggerAction::Send {
+            text: text.to_string(),
+            newline: true,
+        }
+    }
+
+    /// What fired since last checked.
+    fn matches(rx_fired: &mut mpsc::UnboundedReceiver<FiredTrigger>) -> Vec<String> {
+        std::iter::from_fn(|| rx_fired.try_recv().ok())
+            .map(|fired| fired.matched)
+            .collect()
+    }
+
+    #[test]
+    fn fires_once_per_match() {
+        let (tx_fired, mut rx_fired) = mpsc::unbounded_channel();
+        let triggers = Triggers::default();
+        let handle = Handle {
+            index: 0,
+            generation: 0,
+        };
+        triggers.attach(handle, tx_fired);
+        triggers.add(trigger(r"Press enter", send(""))).unwrap();
+        triggers
+            .add(trigger(r"flag\{\w+\}", TriggerAction::Bell))
+            .unwrap();
+        assert!(triggers.add(trigger(r"x*", TriggerAction::Bell)).is_err());
+
+        // Prompts can arrive in pieces and without a newline.
+        triggers.feed(b"\x1b[1mPress ");
+        assert!(matches(&mut rx_fired).is_empty());
+        triggers.feed(b"enter to continue");
+        assert_eq!(matches(&mut rx_fired), ["Press enter"]);
+        triggers.feed(b" ");
+        triggers.feed(b"Press enter\r\nPress enter");
+        assert_eq!(matches(&mut rx_fired), ["Press enter", "Press enter"]);
+
+        triggers.feed(b"flag{abc}\r\n");
+        assert_eq!(
+            rx_fired.try_recv().unwrap(),
+            FiredTrigger {
+                handle,
+                action: TriggerAction::Bell,
+                matched: "flag{abc}".to_string(),
+            }
+        );
+        assert!(rx_fired.try_recv().is_err());
+
+        let stored = serde_json::to_string(&triggers.list()).unwrap();
+        let restored: Vec<Trigger> = serde_json::from_str(&stored).unwrap();
+        assert_eq!(restored[0].action, send(""));
+        assert_eq!(restored[1].regex.as_str(), r"flag\{\w+\}");
+        assert_eq!(restored[1].action, TriggerAction::Bell);
+
+        triggers.remove(0).unwrap();
+        triggers.feed(b"Press enter");
+        assert!(matches(&mut rx_fired).is_empty());
+        assert!(triggers.remove(1).is_err());
+    }
+
+    #[test]
+    fn empty_matches() {
+        let (tx_fired, mut rx_fired) = mpsc::unbounded_channel();
+        let triggers = Triggers::default();
+        triggers.attach(
+            Handle {
+                index: 0,
+                generation: 0,
+            },
+            tx_fired,
+        );
+        triggers.add(trigger(r"\b", send("x"))).unwrap();
+        triggers.add(trigger(r"\bc?", send("c"))).unwrap();
+        triggers.feed(b"abc");
+        assert!(matches(&mut rx_fired).is_empty());
+        triggers.feed(b" c");
+        assert_eq!(matches(&mut rx_fired), ["c"]);
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Context, Result};
+use config::{Config, Environment, File};
+use regex::Regex;
+use serde::{de, Deserialize, Deserializer};
+
+use crate::{
+    files,
+    input::{aliases, PromptTemplate},
+};
+
+pub const SETTINGS_FILENAME: &str = "rctf.toml";
+/// Letters that can't be the session prefix, since with Ctrl they are ^C, ^D, backspace, tab or
+/// enter.
+const RESERVED_PREFIXES: [char; 6] = ['c', 'd', 'h', 'i', 'j', 'm'];
+
+/// User settings, read from `rctf.toml` in the config directory and `RCTF_*` environment
+/// variables (e.g. `RCTF_HISTORY__SIZE=500`).
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub history: HistorySettings,
+    pub prompt: PromptSettings,
+    pub notifications: NotificationSettings,
+    pub session: SessionSettings,
+    pub log: LogSettings,
+    /// Aliases like those defined with `alias`, which these take precedence over.
+    pub aliases: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HistorySettings {
+    /// Maximum number of entries kept in each history.
+    pub size: usize,
+    /// Don't record lines starting with a space, e.g. ones containing secrets.
+    pub ignore_space: bool,
+    /// Don't record a line if it repeats the previous entry.
+    pub ignore_dups: bool,
+}
+
+impl Default for HistorySettings {
+    fn default() -> Self {
+        Self {
+            size: 1000,
+            ignore_space: true,
+            ignore_dups: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PromptSettings {
+    pub rctf: PromptTemplate,
+    /// Shown while controlling a session, so it should say which one.
+    pub termcraft: PromptTemplate,
+}
+
+impl Default for PromptSettings {
+    fn default() -> Self {
+        Self {
+            rctf: "{blue}rctf{reset}> ".parse().unwrap(),
+            termcraft: "{blue}termcraft{reset} {cyan}{session}{reset}> "
+                .parse()
+                .unwrap(),
+        }
+    }
+}
+
+/// Notices printed above the prompt about sessions in the background.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct NotificationSettings {
+    /// Announce when a session first prints something while in the background.
+    pub output: bool,
+    /// Announce when a session exits or disconnects.
+    pub exit: bool,
+    /// Regexes to look for in background output, e.g. `flag\{.*\}`.
+    pub patterns: Vec<String>,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        Self {
+            output: true,
+            exit: true,
+            patterns: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SessionSettings {
+    /// Pressed with Ctrl inside a session, followed by a number, `n`, `p` or `l`, to switch to
+    /// another session. Pressing it twice sends it to the session.
+    pub prefix: char,
+    /// Regex for the last line of output when a shell is waiting for input, e.g. `[$#] $`.
+    /// Otherwise the prompt is learned from the first one seen.
+    #[serde(deserialize_with = "deserialize_regex")]
+    pub shell_prompt: Option<Regex>,
+    /// Milliseconds to wait for the shell prompt before carrying on anyway.
+    pub shell_prompt_timeout: u64,
+}
+
+impl Default for SessionSettings {
+    fn default() -> Self {
+        Self {
+            prefix: 'b',
+            shell_prompt: None,
+            shell_prompt_timeout: 2000,
+        }
+    }
+}
+
+/// Transcripts of sessions' input and output, written to `transcripts` in the data directory.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct LogSettings {
+    /// Log new sessions from the start, including anything typed into them like passwords.
+    /// `log on|off` changes this while running.
+    pub enabled: bool,
+}
+
+fn deserialize_regex<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Regex>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|regex| Regex::new(&regex).map_err(de::Error::custom))
+        .transpose()
+}
+
+impl Settings {
+    pub fn load() -> Result<Self> {
+        let mut builder = Config::builder();
+        if let Some(path) = files::config::create_path(SETTINGS_FILENAME) {
+            builder = builder.add_source(File::from(path).required(false));
+        }
+
+        let settings = builder
+            .add_source(Environment::with_prefix("RCTF").separator("__"))
+            .build()?
+            .try_deserialize::<Self>()?;
+        let prefix = settings.session.prefix.to_ascii_lowercase();
+        if !prefix.is_ascii_lowercase() || RESERVED_PREFIXES.contains(&prefix) {
+            bail!(
+                "Invalid session prefix `{prefix}` in {SETTINGS_FILENAME}, use a letter other than \
+                 c, d, h, i, j or m."
+            );
+        }
+        for (name, template) in &settings.aliases {
+            aliases::check(template)
+                .with_context(|| format!("Invalid alias `{name}` in {SETTINGS_FILENAME}."))?;
+        }
+        Ok(settings)
+    }
+}
@@ -1,12 +1,13 @@
-use std::{fmt::Display, sync::Arc};
+use std::{borrow::Cow, sync::Arc, time::Duration};
 
 use anyhow::{anyhow, bail, Result};
 use async_trait::async_trait;
+use chrono::{DateTime, Local};
 use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
 use futures::StreamExt;
 use russh::{
     client::{self, Config, Handle, Msg},
-    Channel, Disconnect, Pty, Sig,
+    Channel, Disconnect, Pty,
 };
 use tokio::{
     io::AsyncWriteExt,
@@ -14,7 +15,13 @@ use tokio::{
     sync::{mpsc, watch},
 };
 
-use crate::session::{Session, SessionExit};
+use crate::{
+    session::{
+        wait_for_prompt, ExitStatus, FiredTrigger, OutputBuffer, PromptPattern, Recording, Session,
+        SessionExit, SessionState, SessionStats, SwitchTarget, Tags, Transcript, Triggers,
+    },
+    settings::SessionSettings,
+};
 
 mod handler;
 use self::handler::Handler;
@@ -31,31 +38,25 @@ pub struct SshSettings {
     pub password: String,
 }
 
-#[derive(Debug, Clone)]
-enum Exit {
-    Status(u32),
-    Signal(Sig, String),
-}
-
 enum Status {
     Disconnected,
     Connected {
         session: Handle<Handler>,
         channel: Channel<Msg>,
-        rx_exit: mpsc::Receiver<Exit>,
-        rx_stdout: watch::Receiver<Vec<u8>>,
-        rx_stderr: watch::Receiver<Vec<u8>>,
     },
 }
 
-impl Display for Exit {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Exit::Status(code) => write!(f, "Process exited with code {code}."),
-            Exit::Signal(signal, reason) => {
-                write!(f, "Process exited with signal SIG{signal:?}: {reason}")
-            }
-        }
+/// Data sent to the remote end.
+#[derive(Debug, Clone, Copy, Default)]
+struct Sent {
+    bytes: usize,
+    at: Option<DateTime<Local>>,
+}
+
+impl Sent {
+    fn record(&mut self, len: usize) {
+        self.bytes += len;
+        self.at = Some(Local::now());
     }
 }
 
@@ -66,6 +67,17 @@ pub struct SshSession {
     password: String,
     status: Status,
     name: String,
+    tags: Tags,
+    transcript: Transcript,
+    recording: Recording,
+    triggers: Triggers,
+    /// The shell prompt, learned when it is first reset.
+    prompt: Option<PromptPattern>,
+    tx_output: Arc<watch::Sender<OutputBuffer>>,
+    tx_exit: Arc<watch::Sender<Option<ExitStatus>>>,
+    printed: usize,
+    connected_at: Option<DateTime<Local>>,
+    sent: Sent,
 }
 
 impl SshSession {
@@ -77,6 +89,16 @@ impl SshSession {
             password: settings.password,
             status: Status::Disconnected,
             name: String::new(),
+            tags: Tags::default(),
+            transcript: Transcript::default(),
+            recording: Recording::default(),
+            triggers: Triggers::default(),
+            prompt: None,
+            tx_output: Arc::new(watch::channel(OutputBuffer::default()).0),
+            tx_exit: Arc::new(watch::channel(None).0),
+            printed: 0,
+            connected_at: None,
+            sent: Sent::default(),
         }
     }
 
@@ -102,12 +124,15 @@ impl Session for SshSession {
     }
 
     async fn connect(&mut self) -> Result<()> {
-        let (tx_exit, rx_exit) = mpsc::channel(1);
-        let (tx_stdout, rx_stdout) = watch::channel(vec![]);
-        let (tx_stderr, rx_stderr) = watch::channel(vec![]);
-
+        self.tx_exit.send_replace(None);
         let session = self
-            .create_session(Handler::new(tx_exit, tx_stdout, tx_stderr))
+            .create_session(Handler::new(
+                self.tx_exit.clone(),
+                self.tx_output.clone(),
+                self.transcript.clone(),
+                self.recording.clone(),
+                self.triggers.clone(),
+            ))
             .await?;
         let mut channel = session.channel_open_session().await?;
         channel
@@ -128,62 +153,42 @@ impl Session for SshSession {
             .await?;
         channel.request_shell(true).await?;
 
-        self.status = Status::Connected {
-            session,
-            channel,
-            rx_exit,
-            rx_stdout,
-            rx_stderr,
-        };
+        self.status = Status::Connected { session, channel };
+        self.connected_at = Some(Local::now());
 
         Ok(())
     }
 
-    async fn start_read_loop(&mut self) -> Result<SessionExit> {
+    async fn start_read_loop(
+        &mut self,
+        settings: &SessionSettings,
+        rx_fired: &mut mpsc::UnboundedReceiver<FiredTrigger>,
+    ) -> Result<SessionExit> {
         let Status::Connected {
-            ref mut channel,
-            ref mut rx_exit,
-            ref rx_stdout,
-            ref rx_stderr,
-            ..
+            ref mut channel, ..
         } = self.status
         else {
             bail!("Cannot start read loop before connecting");
         };
 
-        let print_loop_handle = {
-            let mut rx_stdout = rx_stdout.clone();
-            let mut rx_stderr = rx_stderr.clone();
-
-            tokio::spawn(async move {
-                loop {
-                    select! {
-                        res = rx_stdout.changed() => {
-                            if let Err(_) = res {
-                                break;
-                            }
-
-                            let msg = rx_stdout.borrow_and_update().clone();
-                            let mut stdout = tokio::io::stdout();
-                            stdout.write(&msg).await.ok();
-                            stdout.flush().await.ok();
-                        }
-                        res = rx_stderr.changed() => {
-                            if let Err(_) = res {
-                                break;
-                            }
+        let mut stdout = tokio::io::stdout();
+        let mut rx_output = self.tx_output.subscribe();
+        let mut rx_exit = self.tx_exit.subscribe();
 
-                            let msg = rx_stdout.borrow_and_update().clone();
-                            let mut stderr = tokio::io::stderr();
-                            stderr.write(&msg).await.ok();
-                            stderr.flush().await.ok();
-                        }
-                    }
-                }
-            })
+        // Print anything that arrived while the session was in the background.
+        let backlog = {
+            let output = rx_output.borrow_and_update();
+            let backlog = output.since(self.printed);
+            self.printed = output.written();
+            backlog
         };
+        stdout.write_all(&backlog).await?;
+        stdout.flush().await?;
 
         let mut reader = EventStream::new();
+        // With Shift or Caps Lock, the key is reported in upper case.
+        let prefix = settings.prefix.to_ascii_lowercase();
+        let mut prefixed = false;
         let res = loop {
             select! {
                 event = reader.next() => {
@@ -191,66 +196,133 @@ impl Session for SshSession {
                         bail!("Out of events.");
                     };
 
-                    if let Event::Key(KeyEvent {
-                        code,
-                        modifiers,
-                        kind: KeyEventKind::Press | KeyEventKind::Repeat,
-                        ..
-                    }) = event?
-                    {
-                        let data: &[u8] = match (code, modifiers) {
+                    let event = event?;
+                    let pressed = matches!(
+                        event,
+                        Event::Key(KeyEvent {
+                            kind: KeyEventKind::Press | KeyEventKind::Repeat,
+                            ..
+                        })
+                    );
+                    // Only the next key press counts as following the prefix.
+                    let after_prefix = pressed && std::mem::take(&mut prefixed);
+
+                    let data: Cow<[u8]> = match event {
+                        // The key after the prefix picks a session to switch to.
+                        Event::Key(KeyEvent {
+                            code,
+                            modifiers,
+                            kind: KeyEventKind::Press | KeyEventKind::Repeat,
+                            ..
+                        }) if after_prefix => {
+                            let target = match (code, modifiers) {
+                                // Pressing the prefix twice sends it through.
+                                (KeyCode::Char(c), _)
+                                    if modifiers.contains(KeyModifiers::CONTROL)
+                                        && c.to_ascii_lowercase() == prefix =>
+                                {
+                                    let data = [c as u8 & 0x1f];
+                                    channel.data(&data[..]).await?;
+                                    self.sent.record(data.len());
+                                    self.transcript.input(&data);
+                                    continue;
+                                }
+                                (KeyCode::Char(c @ '0'..='9'), _) => {
+                                    SwitchTarget::Index(c as usize - '0' as usize)
+                                }
+                                (KeyCode::Char('n'), _) => SwitchTarget::Next,
+                                (KeyCode::Char('p'), _) => SwitchTarget::Previous,
+                                (KeyCode::Char('l'), _) => SwitchTarget::Last,
+                                _ => continue,
+                            };
+                            break Ok(SessionExit::Switch(target));
+                        }
+                        Event::Key(KeyEvent {
+                            code,
+                            modifiers,
+                            kind: KeyEventKind::Press | KeyEventKind::Repeat,
+                            ..
+                        }) => match (code, modifiers) {
                             (KeyCode::Esc, _) => break Ok(SessionExit::Termcraft),
-                            (KeyCode::Enter, _) => &[b'\n'],
-                            (KeyCode::Backspace, _) => &[BACKSPACE],
-                            (KeyCode::Tab, _) => &[b'\t'],
-                            (KeyCode::Up, _) => b"\x1b[A",
-                            (KeyCode::Down, _) => b"\x1b[B",
-                            (KeyCode::Right, _) => b"\x1b[C",
-                            (KeyCode::Left, _) => b"\x1b[D",
-                            (KeyCode::Char('c'), KeyModifiers::CONTROL) => &[ETX],
-                            (KeyCode::Char('d'), KeyModifiers::CONTROL) => &[EOT],
-                            (KeyCode::Char(c), KeyModifiers::NONE | KeyModifiers::SHIFT) => {
-                                channel.data(&[c as u8][..]).await?;
+                            (KeyCode::Char(c), _)
+                                if modifiers.contains(KeyModifiers::CONTROL)
+                                    && c.to_ascii_lowercase() == prefix =>
+                            {
+                                prefixed = true;
                                 continue;
                             }
+                            (KeyCode::Enter, _) => b"\n"[..].into(),
+                            (KeyCode::Backspace, _) => vec![BACKSPACE].into(),
+                            (KeyCode::Tab, _) => b"\t"[..].into(),
+                            (KeyCode::Up, _) => b"\x1b[A"[..].into(),
+                            (KeyCode::Down, _) => b"\x1b[B"[..].into(),
+                            (KeyCode::Right, _) => b"\x1b[C"[..].into(),
+                            (KeyCode::Left, _) => b"\x1b[D"[..].into(),
+                            (KeyCode::Char('c'), KeyModifiers::CONTROL) => vec![ETX].into(),
+                            (KeyCode::Char('d'), KeyModifiers::CONTROL) => vec![EOT].into(),
+                            (KeyCode::Char(c), KeyModifiers::NONE | KeyModifiers::SHIFT) => {
+                                c.to_string().into_bytes().into()
+                            }
                             _ => continue,
-                        };
-                        channel.data(data).await?;
+                        },
+                        // Send pastes in one go rather than as individual key presses.
+                        Event::Paste(text) => text.into_bytes().into(),
+                        _ => continue,
+                    };
+                    channel.data(&data[..]).await?;
+                    self.sent.record(data.len());
+                    self.transcript.input(&data);
+                }
+                res = rx_output.changed() => {
+                    if res.is_err() {
+                        continue;
                     }
+
+                    let msg = {
+                        let output = rx_output.borrow_and_update();
+                        let msg = output.since(self.printed);
+                        self.printed = output.written();
+                        msg
+                    };
+                    stdout.write_all(&msg).await.ok();
+                    stdout.flush().await.ok();
                 }
-                exit = rx_exit.recv() => {
-                    let Some(exit) = exit else {
+                Some(fired) = rx_fired.recv() => break Ok(SessionExit::Trigger(fired)),
+                // Also fires straight away if the session exited while in the background.
+                exited = async { rx_exit.wait_for(Option::is_some).await.is_ok() } => {
+                    if !exited {
                         break Err(anyhow!("Failed to get exit status."));
-                    };
-                    if let Exit::Status(0) = exit {
-                        break Ok(SessionExit::Exit);
                     }
-
-                    break Err(anyhow!(exit));
+                    break Ok(SessionExit::Exit);
                 }
             }
         };
 
-        print_loop_handle.abort();
-        print_loop_handle.await.ok();
-
         res
     }
 
-    async fn reset_prompt(&mut self) -> Result<()> {
+    async fn reset_prompt(&mut self, settings: &SessionSettings) -> Result<()> {
         let Status::Connected {
-            ref mut channel,
-            ref mut rx_stdout,
-            ref mut rx_stderr,
-            ..
+            ref mut channel, ..
         } = self.status
         else {
             bail!("Cannot send data before connecting");
         };
+
+        let mut rx_output = self.tx_output.subscribe();
+        let start = rx_output.borrow_and_update().written();
         channel.data(&[ETX][..]).await?;
-        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-        rx_stdout.borrow_and_update();
-        rx_stderr.borrow_and_update();
+        self.sent.record(1);
+        self.transcript.input(&[ETX]);
+
+        let pattern = self.prompt(settings);
+        let timeout = Duration::from_millis(settings.shell_prompt_timeout);
+        let found = wait_for_prompt(&mut rx_output, start, pattern.as_ref(), timeout).await;
+        if pattern.is_none() {
+            self.prompt = found;
+        }
+
+        self.printed = self.tx_output.borrow().written();
         Ok(())
     }
 
@@ -262,6 +334,8 @@ impl Session for SshSession {
             bail!("Cannot send data before connecting");
         };
         channel.data(data).await?;
+        self.sent.record(data.len());
+        self.transcript.input(data);
         Ok(())
     }
 
@@ -275,15 +349,59 @@ impl Session for SshSession {
             return Ok(());
         };
 
-        channel.eof().await?;
-        session
-            .disconnect(Disconnect::ByApplication, "User exited.", "en")
-            .await?;
-        println!();
+        // There is nothing left to say goodbye to if the remote end has already gone.
+        let exited = self.tx_exit.borrow().is_some();
+        let res: Result<()> = async {
+            if !exited {
+                channel.eof().await?;
+                session
+                    .disconnect(Disconnect::ByApplication, "User exited.", "en")
+                    .await?;
+                println!();
+            }
+            Ok(())
+        }
+        .await;
 
+        // The session is closed even if saying goodbye failed.
         self.status = Status::Disconnected;
+        self.tx_exit.send_if_modified(|exit| {
+            let closing = exit.is_none();
+            if closing {
+                *exit = Some(ExitStatus::Closed);
+            }
+            closing
+        });
 
-        Ok(())
+        res
+    }
+
+    fn host(&self) -> &str {
+        &self.hostname
+    }
+
+    fn remote(&self) -> String {
+        format!("{}@{}:{}", self.username, self.hostname, self.port)
+    }
+
+    fn state(&self) -> SessionState {
+        if self.tx_exit.borrow().is_some() {
+            return SessionState::Closed;
+        }
+        match self.status {
+            Status::Connected { .. } => SessionState::Connected,
+            Status::Disconnected => SessionState::Disconnected,
+        }
+    }
+
+    fn stats(&self) -> SessionStats {
+        let output = self.tx_output.borrow();
+        SessionStats {
+            connected_at: self.connected_at,
+            bytes_in: output.written(),
+            bytes_out: self.sent.bytes,
+            last_activity: output.updated().max(self.sent.at),
+        }
     }
 
     fn name(&self) -> Option<&str> {
@@ -297,4 +415,39 @@ impl Session for SshSession {
     fn name_mut(&mut self) -> &mut String {
         &mut self.name
     }
+
+    fn tags(&self) -> &Tags {
+        &self.tags
+    }
+
+    fn tags_mut(&mut self) -> &mut Tags {
+        &mut self.tags
+    }
+
+    fn transcript(&self) -> &Transcript {
+        &self.transcript
+    }
+
+    fn recording(&self) -> &Recording {
+        &self.recording
+    }
+
+    fn triggers(&self) -> &Triggers {
+        &self.triggers
+    }
+
+    fn prompt(&self, settings: &SessionSettings) -> Option<PromptPattern> {
+        match &settings.shell_prompt {
+            Some(regex) => Some(PromptPattern::Regex(regex.clone())),
+            None => self.prompt.clone(),
+        }
+    }
+
+    fn output(&self) -> watch::Receiver<OutputBuffer> {
+        self.tx_output.subscribe()
+    }
+
+    fn exit(&self) -> watch::Receiver<Option<ExitStatus>> {
+        self.tx_exit.subscribe()
+    }
 }
@@ -4,28 +4,54 @@ use russh::{
     ChannelId, Disconnect, Sig,
 };
 use russh_keys::key;
-use tokio::sync::{mpsc, watch};
+use std::sync::Arc;
 
-use super::Exit;
+use tokio::sync::watch;
+
+use crate::session::{ExitStatus, OutputBuffer, Recording, Transcript, Triggers};
 
 pub(super) struct Handler {
-    tx_exit: mpsc::Sender<Exit>,
-    tx_stdout: watch::Sender<Vec<u8>>,
-    tx_stderr: watch::Sender<Vec<u8>>,
+    tx_exit: Arc<watch::Sender<Option<ExitStatus>>>,
+    tx_output: Arc<watch::Sender<OutputBuffer>>,
+    transcript: Transcript,
+    recording: Recording,
+    triggers: Triggers,
 }
 
 impl Handler {
     pub(super) fn new(
-        tx_exit: mpsc::Sender<Exit>,
-        tx_stdout: watch::Sender<Vec<u8>>,
-        tx_stderr: watch::Sender<Vec<u8>>,
+        tx_exit: Arc<watch::Sender<Option<ExitStatus>>>,
+        tx_output: Arc<watch::Sender<OutputBuffer>>,
+        transcript: Transcript,
+        recording: Recording,
+        triggers: Triggers,
     ) -> Self {
         Self {
             tx_exit,
-            tx_stdout,
-            tx_stderr,
+            tx_output,
+            transcript,
+            recording,
+            triggers,
         }
     }
+
+    fn output(&self, data: &[u8]) {
+        self.transcript.output(data);
+        self.recording.output(data);
+        self.tx_output.send_modify(|output| output.push(data));
+        self.triggers.feed(data);
+    }
+
+    /// Record how the session ended, unless that is already known.
+    fn exit(&self, status: ExitStatus) {
+        self.tx_exit.send_if_modified(|exit| {
+            if exit.is_some() {
+                return false;
+            }
+            *exit = Some(status);
+            true
+        });
+    }
 }
 
 #[async_trait]
@@ -45,7 +71,7 @@ impl RusshHandler for Handler {
         data: &[u8],
         session: Session,
     ) -> core::result::Result<(Self, Session), Self::Error> {
-        self.tx_stdout.send(data.to_vec())?;
+        self.output(data);
         Ok((self, session))
     }
 
@@ -56,12 +82,21 @@ impl RusshHandler for Handler {
         data: &[u8],
         session: Session,
     ) -> core::result::Result<(Self, Session), Self::Error> {
-        self.tx_stderr.send(data.to_vec())?;
+        self.output(data);
+        Ok((self, session))
+    }
+
+    async fn channel_close(
+        self,
+        _channel: ChannelId,
+        session: Session,
+    ) -> core::result::Result<(Self, Session), Self::Error> {
+        self.exit(ExitStatus::Closed);
         Ok((self, session))
     }
 
     async fn exit_status(
-        mut self,
+        self,
         channel: ChannelId,
         exit_status: u32,
         mut session: Session,
@@ -72,12 +107,12 @@ impl RusshHandler for Handler {
             "Process exited with status.",
             "en",
         );
-        self.tx_exit.send(Exit::Status(exit_status)).await.ok();
+        self.exit(ExitStatus::Code(exit_status));
         Ok((self, session))
     }
 
     async fn exit_signal(
-        mut self,
+        self,
         channel: ChannelId,
         signal_name: Sig,
         _core_dumped: bool,
@@ -91,10 +126,10 @@ impl RusshHandler for Handler {
             "Process exited with signal.",
             "en",
         );
-        self.tx_exit
-            .send(Exit::Signal(signal_name, error_message.to_string()))
-            .await
-            .ok();
+        self.exit(ExitStatus::Signal(
+            format!("{signal_name:?}"),
+            error_message.to_string(),
+        ));
         Ok((self, session))
     }
 }
@@ -1,28 +1,40 @@
 use crate::{
-    commands::Commands,
+    commands::{parse_command, Commands},
+    input::PromptKind,
+    session::{Trigger, TriggerAction},
     terminal::{eprintln_colored, println},
+    util::table_settings,
+    variables::{expr::parse_int, Value},
     Context,
 };
 use anyhow::{bail, Result};
-use clap::{command, Parser, Subcommand};
+use clap::{Parser, Subcommand};
 use crossterm::style::Color;
+use regex::Regex;
+use tabled::Table;
+
+mod expect;
+pub(crate) use self::expect::literal;
+
+/// Seconds to wait for output in `expect`, `sendafter` and `recvuntil` by default.
+pub(crate) const DEFAULT_TIMEOUT: f64 = 10.0;
 
 pub enum TermcraftResponse {
-    Cmd(String),
+    Cmd(Vec<u8>),
     Background,
     Exit,
 }
 
 #[derive(Debug, Parser)]
 #[command(multicall = true)]
-struct Termcraft {
+pub(crate) struct Termcraft {
     #[command(subcommand)]
-    command: TermcraftCommands,
+    pub(crate) command: TermcraftCommands,
 }
 
 // TODO: base64, hex, xor, etc.
 #[derive(Debug, Subcommand)]
-enum TermcraftCommands {
+pub(crate) enum TermcraftCommands {
     /// Send current session to background
     #[command(alias = "background")]
     Bg,
@@ -31,78 +43,347 @@ enum TermcraftCommands {
         /// The name to change this session to
         name: Option<String>,
     },
+    /// Store part of the session's recent output in a variable
+    ///
+    /// The last match of the regex is used, and the first capture group is stored if there is one.
+    #[command(group = clap::ArgGroup::new("source").required(true))]
+    Grab {
+        /// The variable to store the output in
+        name: String,
+        /// Regex to search the output for, e.g. `puts@ (0x[0-9a-f]+)`
+        #[arg(group = "source")]
+        regex: Option<String>,
+        /// Grab the last complete line of output instead, ignoring the current prompt
+        #[arg(long, group = "source")]
+        last_line: bool,
+        /// Store the output as an integer, e.g. `0x7ffff7a62aa0`
+        #[arg(short, long)]
+        int: bool,
+    },
     /// Terminal-style printf (man 1 printf)
     Printf {
         /// Format string
         ///
-        /// Variables can be used like `#variable` or `#{variable}`.
+        /// Variables can be used like `#variable`, and expressions like `#{base + 0x18}`,
+        /// `#{len(payload)}` or `#{p64(addr)}`.
         ///
         /// Escaped sequences include:
         /// `\\`        backslash
         /// `\n`        new line
         /// `\r`        carriage return
         /// `\t`        horizontal tab
+        /// `\0`        null byte
         /// `\#`        hashtag
-        // TODO:
-        // `\xHH`      byte with hex value HH
-        // `\uHHHH`    Unicode character with hex value HHHH
+        /// `\xHH`      byte with hex value HH
+        /// `\uHHHH`    Unicode character with hex value HHHH
         format_string: String,
     },
+    /// Wait for output matching a regex, consuming output up to the end of the match
+    Expect {
+        /// Regex to wait for, e.g. `[Pp]assword:`
+        regex: String,
+        /// Seconds to wait before giving up
+        #[arg(short, long, default_value_t = DEFAULT_TIMEOUT)]
+        timeout: f64,
+        /// Store the first capture group, or the whole match, in this variable
+        #[arg(short, long)]
+        var: Option<String>,
+    },
+    /// Send a line to the session without leaving termcraft
+    Sendline {
+        /// Text to send, which can use variables, expressions and escapes like `printf`. Variables
+        /// are filled in each time the trigger fires.
+        text: String,
+    },
+    /// Wait for output matching a regex, then send text
+    Sendafter {
+        /// Regex to wait for, e.g. `> $`
+        regex: String,
+        /// Text to send, which can use variables, expressions and escapes like `printf`
+        text: String,
+        /// Append a newline, like pwntools' `sendlineafter`
+        #[arg(short, long)]
+        line: bool,
+        /// Seconds to wait before giving up
+        #[arg(short, long, default_value_t = DEFAULT_TIMEOUT)]
+        timeout: f64,
+    },
+    /// Print output up to and including a delimiter, consuming it
+    Recvuntil {
+        /// Delimiter to wait for, which can use variables and escapes like `printf`
+        delimiter: String,
+        /// Seconds to wait before giving up
+        #[arg(short, long, default_value_t = DEFAULT_TIMEOUT)]
+        timeout: f64,
+        /// Store the received output in this variable
+        #[arg(short, long)]
+        var: Option<String>,
+    },
+    /// List, add or remove triggers, which respond automatically to the session's output
+    ///
+    /// Triggers are saved for the session's remote, e.g. `root@10.0.0.5:22`, and added again to
+    /// new sessions with the same remote.
+    Trigger {
+        #[command(subcommand)]
+        command: Option<TriggerCommands>,
+    },
 
     #[command(flatten)]
     Command(Commands),
 }
 
+#[derive(Debug, Subcommand)]
+pub(crate) enum TriggerCommands {
+    /// List this session's triggers
+    #[command(alias = "ls")]
+    List,
+    /// Add a trigger, which sends a response whenever the output matches
+    Add {
+        /// Regex to look for in the output, e.g. `Press enter to continue`
+        regex: String,
+        /// Text to send, which can use variables, expressions and escapes like `printf`
+        #[arg(required_unless_present = "bell")]
+        response: Option<String>,
+        /// Don't append a newline to the response
+        #[arg(short, long)]
+        no_newline: bool,
+        /// Run the response as an rctf command instead, e.g. `var set shell 1`
+        #[arg(short, long, conflicts_with = "no_newline")]
+        command: bool,
+        /// Ring the terminal bell instead of responding
+        #[arg(short, long, conflicts_with_all = ["response", "no_newline", "command"])]
+        bell: bool,
+    },
+    /// Remove a trigger, or all triggers
+    #[command(alias = "rm")]
+    Remove {
+        /// The index of the trigger, as shown by `trigger list`
+        index: Option<usize>,
+    },
+}
+
 impl<'a> Context<'a> {
     pub async fn start_termcraft(&mut self, session_index: usize) -> Result<TermcraftResponse> {
-        const PROMPT: &str = "termcraft";
-
         if self.sessions.get(session_index).is_none() {
             bail!("Could not find session with index {session_index}.");
         };
+        // How far `expect` and friends have read, starting from what has already been shown.
+        let mut position = self
+            .sessions
+            .get(session_index)
+            .unwrap()
+            .output()
+            .borrow()
+            .written();
 
         loop {
-            let mut new_history = self.termcraft_history.clone();
-            let res = self.get_next_command(PROMPT, &mut new_history).await;
-            self.termcraft_history = new_history;
+            let res = self
+                .get_next_command(PromptKind::Termcraft, Some(session_index))
+                .await;
 
             let cmd: Termcraft = match res {
                 Ok(Some(cmd)) => cmd,
-                Ok(None) => Termcraft { command: TermcraftCommands::Command(Commands::Exit) },
+                Ok(None) => Termcraft {
+                    command: TermcraftCommands::Command(Commands::Exit),
+                },
                 Err(e) => {
                     eprintln_colored(e, Color::Red)?;
                     continue;
                 }
             };
 
-            match cmd.command {
-                TermcraftCommands::Bg => return Ok(TermcraftResponse::Background),
-                TermcraftCommands::Name { name } => {
-                    if let Some(name) = name {
-                        *self.sessions.get_mut(session_index).unwrap().name_mut() = name.clone();
-                        self.named_sessions.insert(name, session_index);
-                    }
-                    println(
-                        self.sessions
-                            .get(session_index)
-                            .unwrap()
-                            .name()
-                            .unwrap_or("This session is currently unnamed."),
-                    )?;
+            match self
+                .termcraft_command(session_index, cmd.command, &mut position)
+                .await
+            {
+                Ok(Some(response)) => return Ok(response),
+                Ok(None) => {}
+                Err(e) => eprintln_colored(e, Color::Red)?,
+            }
+        }
+    }
+
+    /// Run a termcraft command on a session, returning how to leave termcraft if it does.
+    /// `position` is how far `expect` and friends have read the session's output.
+    pub(crate) async fn termcraft_command(
+        &mut self,
+        session_index: usize,
+        command: TermcraftCommands,
+        position: &mut usize,
+    ) -> Result<Option<TermcraftResponse>> {
+        match command {
+            TermcraftCommands::Bg => return Ok(Some(TermcraftResponse::Background)),
+            TermcraftCommands::Name { name } => {
+                if let Some(name) = name {
+                    self.rename_session(session_index, name)?;
                 }
-                TermcraftCommands::Printf { format_string } => {
-                    let cmd = match self.parse_line(&format_string) {
-                        Ok(cmd) => cmd,
-                        Err(e) => {
-                            eprintln_colored(e, Color::Red)?;
-                            continue;
-                        }
-                    };
-                    return Ok(TermcraftResponse::Cmd(cmd));
+                println(
+                    self.sessions
+                        .get(session_index)
+                        .unwrap()
+                        .name()
+                        .unwrap_or("This session is currently unnamed."),
+                )?;
+            }
+            TermcraftCommands::Grab {
+                name,
+                regex,
+                last_line,
+                int,
+            } => self.grab(session_index, name, regex, last_line, int)?,
+            TermcraftCommands::Printf { format_string } => {
+                let cmd = self.parse_line(&format_string)?;
+                return Ok(Some(TermcraftResponse::Cmd(cmd)));
+            }
+            TermcraftCommands::Expect {
+                regex,
+                timeout,
+                var,
+            } => {
+                let regex = regex::bytes::Regex::new(&regex)?;
+                let received = self
+                    .receive_until(session_index, position, &regex, timeout)
+                    .await?;
+                println(&received.matched)?;
+                self.store(var, received.matched);
+            }
+            TermcraftCommands::Sendline { text } => {
+                self.send_text(session_index, &text, true).await?;
+            }
+            TermcraftCommands::Sendafter {
+                regex,
+                text,
+                line,
+                timeout,
+            } => {
+                let regex = regex::bytes::Regex::new(&regex)?;
+                self.receive_until(session_index, position, &regex, timeout)
+                    .await?;
+                self.send_text(session_index, &text, line).await?;
+            }
+            TermcraftCommands::Recvuntil {
+                delimiter,
+                timeout,
+                var,
+            } => {
+                let regex = literal(&self.parse_line(&delimiter)?)?;
+                let received = self
+                    .receive_until(session_index, position, &regex, timeout)
+                    .await?;
+                println(&received.data)?;
+                self.store(var, received.data);
+            }
+            TermcraftCommands::Trigger { command } => {
+                self.trigger(session_index, command.unwrap_or(TriggerCommands::List))?;
+            }
+            TermcraftCommands::Command(Commands::Exit) => {
+                return Ok(Some(TermcraftResponse::Exit));
+            }
+            TermcraftCommands::Command(command) => {
+                self.handle_command(command, PromptKind::Termcraft, Some(session_index))
+                    .await?;
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn grab(
+        &mut self,
+        session_index: usize,
+        name: String,
+        regex: Option<String>,
+        last_line: bool,
+        int: bool,
+    ) -> Result<()> {
+        let Some(session) = self.sessions.get(session_index) else {
+            bail!("Could not find session with index {session_index}.");
+        };
+        let output = session.output().borrow().clone();
+
+        let grabbed = if last_line {
+            let Some(line) = output.last_line() else {
+                bail!("There is no output to grab.");
+            };
+            line
+        } else {
+            let regex = Regex::new(&regex.unwrap_or_default())?;
+            let text = output.text();
+            let Some(captures) = regex.captures_iter(&text).last() else {
+                bail!("No match found for `{regex}`.");
+            };
+            captures
+                .get(1)
+                .or_else(|| captures.get(0))
+                .map(|m| m.as_str().to_owned())
+                .unwrap_or_default()
+        };
+
+        let value = if int {
+            Value::Int(parse_int(grabbed.trim())?)
+        } else {
+            Value::Str(grabbed)
+        };
+        println(&value)?;
+        self.variables.insert(name, value);
+
+        Ok(())
+    }
+
+    fn trigger(&mut self, session_index: usize, command: TriggerCommands) -> Result<()> {
+        let Some(session) = self.sessions.get(session_index) else {
+            bail!("Could not find session with index {session_index}.");
+        };
+        let triggers = session.triggers().clone();
+
+        match command {
+            TriggerCommands::List => {
+                let list = triggers.list();
+                if list.is_empty() {
+                    println("This session has no triggers.")?;
+                    return Ok(());
                 }
-                TermcraftCommands::Command(Commands::Exit) => return Ok(TermcraftResponse::Exit),
-                TermcraftCommands::Command(command) => self.handle_command(command).await?,
+
+                let mut table = Table::builder(list.iter().enumerate().map(|(i, trigger)| {
+                    (i, trigger.regex.to_string(), trigger.action.to_string())
+                }));
+                table.set_header(["index", "regex", "action"]);
+
+                let table = table.build().with(table_settings()).to_string();
+                println(table)?;
+            }
+            TriggerCommands::Add {
+                regex,
+                response,
+                no_newline,
+                command,
+                bell,
+            } => {
+                let response = response.unwrap_or_default();
+                let action = if bell {
+                    TriggerAction::Bell
+                } else if command {
+                    // Catch mistakes now rather than every time it fires.
+                    parse_command(&response)?;
+                    TriggerAction::Command(response)
+                } else {
+                    TriggerAction::Send {
+                        text: response,
+                        newline: !no_newline,
+                    }
+                };
+                triggers.add(Trigger {
+                    regex: Regex::new(&regex)?,
+                    action,
+                })?;
+            }
+            TriggerCommands::Remove { index: Some(index) } => {
+                triggers.remove(index)?;
             }
+            TriggerCommands::Remove { index: None } => triggers.clear(),
         }
+        triggers.save(&session.remote())?;
+
+        Ok(())
     }
 }
@@ -0,0 +1,152 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use regex::bytes::Regex;
+use tokio::{
+    select,
+    time::{timeout_at, Instant},
+};
+
+use crate::{terminal::strip_escapes_with_offsets, variables::Value, Context};
+
+/// Output received from a session up to the end of a match, like pwntools' `recvuntil`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Received {
+    /// Everything received, with terminal escapes removed.
+    pub(crate) data: String,
+    /// The first capture group of the match, or the whole match if there are none.
+    pub(crate) matched: String,
+    /// How many bytes of raw output were consumed.
+    pub(crate) consumed: usize,
+}
+
+impl<'a> Context<'a> {
+    /// Wait for the session's output after `position` to match `regex`, moving `position` past
+    /// the match.
+    pub(crate) async fn receive_until(
+        &mut self,
+        session_index: usize,
+        position: &mut usize,
+        regex: &Regex,
+        timeout: f64,
+    ) -> Result<Received> {
+        let Ok(timeout) = Duration::try_from_secs_f64(timeout) else {
+            bail!("The timeout must be a positive number of seconds.");
+        };
+        let Some(session) = self.sessions.get(session_index) else {
+            bail!("Could not find session with index {session_index}.");
+        };
+        let mut rx_output = session.output();
+        let rx_exit = session.exit();
+
+        let deadline = Instant::now() + timeout;
+        // Triggers that send a response are answered while waiting, since that may be what the
+        // output is waiting for. The rest are run once rctf is free again.
+        let mut deferred = Vec::new();
+        let res = loop {
+            let (output, written) = {
+                let output = rx_output.borrow_and_update();
+                (output.since(*position), output.written())
+            };
+            if let Some(received) = find(&output, regex) {
+                // Output that no longer fits in the buffer is skipped over.
+                *position = written - output.len() + received.consumed;
+                break Ok(received);
+            }
+            if rx_exit.borrow().is_some() {
+                break Err(anyhow!("The session exited before `{regex}` was received."));
+            }
+            select! {
+                changed = timeout_at(deadline, rx_output.changed()) => match changed {
+                    Ok(Ok(())) => continue,
+                    Ok(Err(_)) => break Err(anyhow!("The session is gone.")),
+                    Err(_) => break Err(anyhow!("Timed out waiting for `{regex}`.")),
+                },
+                Some(fired) = self.rx_fired.recv() => {
+                    deferred.extend(self.answer_trigger(fired).await?);
+                }
+            }
+        };
+        for fired in deferred {
+            self.tx_fired.send(fired).ok();
+        }
+        res
+    }
+
+    /// Send text to the session, interpolating variables and escapes.
+    pub(crate) async fn send_text(
+        &mut self,
+        session_index: usize,
+        text: &str,
+        newline: bool,
+    ) -> Result<()> {
+        let mut data = self.parse_line(text)?;
+        if newline {
+            data.push(b'\n');
+        }
+        let Some(session) = self.sessions.get_mut(session_index) else {
+            bail!("Could not find session with index {session_index}.");
+        };
+        session.send(&data).await
+    }
+
+    pub(crate) fn store(&mut self, name: Option<String>, value: String) {
+        if let Some(name) = name {
+            self.variables.insert(name, Value::Str(value));
+        }
+    }
+}
+
+/// A regex that matches `delimiter` literally, including bytes that aren't valid UTF-8.
+pub(crate) fn literal(delimiter: &[u8]) -> Result<Regex> {
+    let pattern: String = delimiter
+        .iter()
+        .map(|byte| format!("\\x{byte:02x}"))
+        .collect();
+    Ok(Regex::new(&format!("(?-u){pattern}"))?)
+}
+
+/// Find the first match of `regex` in terminal output, ignoring escape sequences.
+fn find(output: &[u8], regex: &Regex) -> Option<Received> {
+    let (text, offsets) = strip_escapes_with_offsets(output);
+    let captures = regex.captures(&text)?;
+    let whole = captures.get(0)?;
+    let matched = captures.get(1).unwrap_or(whole);
+    let consumed = match whole.end() {
+        0 => 0,
+        end => offsets[end - 1],
+    };
+    Some(Received {
+        data: String::from_utf8_lossy(&text[..whole.end()]).into_owned(),
+        matched: String::from_utf8_lossy(matched.as_bytes()).into_owned(),
+        consumed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_matches() {
+        let output = b"Welcome\r\n\x1b[1mlogin:\x1b[0m user\r\nPassword: ";
+        let received = find(output, &Regex::new(r"login:").unwrap()).unwrap();
+        assert_eq!(received.data, "Welcome\nlogin:");
+        assert_eq!(received.matched, "login:");
+        assert_eq!(&output[received.consumed..], b"\x1b[0m user\r\nPassword: ");
+
+        let received = find(output, &Regex::new(r"(\w+): $").unwrap()).unwrap();
+        assert_eq!(received.matched, "Password");
+        assert_eq!(received.consumed, output.len());
+
+        assert!(find(output, &Regex::new("shell").unwrap()).is_none());
+    }
+
+    #[test]
+    fn literals() {
+        let regex = literal(b"a.b\xff").unwrap();
+        assert!(regex.is_match(b"xa.b\xffy"));
+        assert!(!regex.is_match(b"axb\xff"));
+        assert!(!regex.is_match("a.bÿ".as_bytes()));
+    }
+}
@@ -55,6 +55,55 @@ pub fn teardown(supports_keyboard_enhancement: bool) -> Result<()> {
     Ok(())
 }
 
+/// Remove ANSI escape sequences and carriage returns from terminal output.
+pub fn strip_escapes(data: &[u8]) -> Vec<u8> {
+    strip_escapes_with_offsets(data).0
+}
+
+/// Like [`strip_escapes`], also giving for each byte kept the offset in `data` just after it, so
+/// that a position in the stripped output can be mapped back.
+pub fn strip_escapes_with_offsets(data: &[u8]) -> (Vec<u8>, Vec<usize>) {
+    const ESC: u8 = 0x1b;
+    const BEL: u8 = 0x07;
+
+    let mut res = Vec::with_capacity(data.len());
+    let mut offsets = Vec::with_capacity(data.len());
+    let mut bytes = data.iter().copied().enumerate().peekable();
+
+    while let Some((i, byte)) = bytes.next() {
+        match byte {
+            ESC => match bytes.next() {
+                // CSI: parameters until a final byte in 0x40..=0x7e
+                Some((_, b'[')) => {
+                    for (_, byte) in bytes.by_ref() {
+                        if (0x40..=0x7e).contains(&byte) {
+                            break;
+                        }
+                    }
+                }
+                // OSC: until BEL or ST
+                Some((_, b']')) => {
+                    while let Some((_, byte)) = bytes.next() {
+                        if byte == BEL
+                            || (byte == ESC && bytes.next_if(|(_, byte)| *byte == b'\\').is_some())
+                        {
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            },
+            b'\r' => {}
+            byte => {
+                res.push(byte);
+                offsets.push(i + 1);
+            }
+        }
+    }
+
+    (res, offsets)
+}
+
 pub fn println<T: Display>(item: T) -> Result<()> {
     println_helper(&mut std::io::stdout(), item, None)
 }
@@ -1,8 +1,59 @@
+use chrono::Duration;
 use tabled::settings::{
     style::{HorizontalLine, On},
     Settings, Style,
 };
 
-pub fn table_settings() -> Settings<Settings, Style<On, On, On, On, (), On, [HorizontalLine; 1]>> {
+type TableSettings = Settings<Settings, Style<On, On, On, On, (), On, [HorizontalLine; 1]>>;
+
+pub fn table_settings() -> TableSettings {
     Settings::default().with(Style::rounded())
 }
+
+/// Format a duration compactly, e.g. `1h 02m 03s`.
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.num_seconds().max(0);
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{hours}h {minutes:02}m {seconds:02}s")
+    } else if minutes > 0 {
+        format!("{minutes}m {seconds:02}s")
+    } else {
+        format!("{seconds}s")
+    }
+}
+
+/// Format a byte count with a binary unit, e.g. `1.5 KiB`.
+pub fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(format_duration(Duration::seconds(7)), "7s");
+        assert_eq!(format_duration(Duration::seconds(125)), "2m 05s");
+        assert_eq!(format_duration(Duration::seconds(3723)), "1h 02m 03s");
+    }
+
+    #[test]
+    fn bytes() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024), "3.0 MiB");
+    }
+}
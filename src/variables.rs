@@ -0,0 +1,193 @@
+use std::{collections::HashMap, fmt::Display};
+
+use anyhow::{bail, Result};
+
+pub mod expr;
+
+pub type Variables = HashMap<String, Value>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Int(i128),
+    Str(String),
+    Bytes(Vec<u8>),
+    List(Vec<Value>),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "int",
+            Value::Str(_) => "str",
+            Value::Bytes(_) => "bytes",
+            Value::List(_) => "list",
+        }
+    }
+
+    /// The raw bytes this value expands to when interpolated.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Value::Int(int) => int.to_string().into_bytes(),
+            Value::Str(str) => str.as_bytes().to_vec(),
+            Value::Bytes(bytes) => bytes.clone(),
+            Value::List(list) => {
+                let items: Vec<_> = list.iter().map(Value::to_bytes).collect();
+                items.join(&b' ')
+            }
+        }
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Str(value)
+    }
+}
+
+impl From<i128> for Value {
+    fn from(value: i128) -> Self {
+        Value::Int(value)
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(value: Vec<u8>) -> Self {
+        Value::Bytes(value)
+    }
+}
+
+/// Renders the value so that it is always safe to print, escaping anything that isn't printable
+/// ASCII inside of bytes.
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Int(int) => write!(f, "{int}"),
+            Value::Str(str) => write!(f, "\"{}\"", str.escape_debug()),
+            Value::Bytes(bytes) => {
+                write!(f, "b\"")?;
+                for byte in bytes {
+                    match byte {
+                        b'"' => write!(f, "\\\"")?,
+                        b'\\' => write!(f, "\\\\")?,
+                        b'\n' => write!(f, "\\n")?,
+                        b'\r' => write!(f, "\\r")?,
+                        b'\t' => write!(f, "\\t")?,
+                        0x20..=0x7e => write!(f, "{}", *byte as char)?,
+                        _ => write!(f, "\\x{byte:02x}")?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Value::List(list) => {
+                write!(f, "[")?;
+                for (i, item) in list.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+/// Expand escape sequences and variables in `input`.
+///
+/// Variables can be used like `#variable` or `#{expression}`.
+pub fn interpolate(input: &str, variables: &Variables) -> Result<Vec<u8>> {
+    let mut res = Vec::with_capacity(input.len());
+    let mut chars = input.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                let Some((_, escaped)) = chars.next() else {
+                    bail!("Trailing backslash.");
+                };
+                expr::push_escape(&mut res, escaped, &mut chars.by_ref().map(|(_, c)| c))?;
+            }
+            '#' => {
+                if let Some((_, '{')) = chars.peek() {
+                    chars.next();
+                    let start = i + 2;
+                    let end = expr::find_closing_brace(&input[start..])
+                        .map(|len| start + len)
+                        .ok_or_else(|| anyhow::anyhow!("Unclosed `#{{`."))?;
+                    let value = expr::evaluate(&input[start..end], variables)?;
+                    res.extend(value.to_bytes());
+                    while chars.peek().is_some_and(|(j, _)| *j <= end) {
+                        chars.next();
+                    }
+                } else {
+                    let mut name = String::new();
+                    while let Some((_, c)) =
+                        chars.next_if(|(_, c)| c.is_alphanumeric() || *c == '_')
+                    {
+                        name.push(c);
+                    }
+                    if name.is_empty() {
+                        res.push(b'#');
+                        continue;
+                    }
+                    let Some(value) = variables.get(&name) else {
+                        bail!("Variable `{name}` is currently unset.");
+                    };
+                    res.extend(value.to_bytes());
+                }
+            }
+            c => res.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables() -> Variables {
+        Variables::from([
+            ("name".to_string(), Value::Str("rctf".to_string())),
+            ("base".to_string(), Value::Int(0x1000)),
+            ("payload".to_string(), Value::Bytes(b"AA\x00".to_vec())),
+        ])
+    }
+
+    #[test]
+    fn escapes() {
+        let res = interpolate(r"a\nb\t\\\#\x41é", &variables()).unwrap();
+        assert_eq!(res, "a\nb\t\\#Aé".as_bytes());
+        assert!(interpolate(r"\x+1", &variables()).is_err());
+        assert!(interpolate(r"\u+041", &variables()).is_err());
+    }
+
+    #[test]
+    fn variables_and_expressions() {
+        let res = interpolate("hi #name! #{base + 0x18} #{len(payload)}", &variables()).unwrap();
+        assert_eq!(res, b"hi rctf! 4120 3");
+
+        let res = interpolate("#{p16(base)}#payload", &variables()).unwrap();
+        assert_eq!(res, b"\x00\x10AA\x00");
+    }
+
+    #[test]
+    fn lone_hashtag() {
+        let res = interpolate("# comment", &variables()).unwrap();
+        assert_eq!(res, b"# comment");
+    }
+
+    #[test]
+    fn unset_variable() {
+        assert!(interpolate("#nope", &variables()).is_err());
+        assert!(interpolate("#{nope + 1}", &variables()).is_err());
+        assert!(interpolate("#{base", &variables()).is_err());
+    }
+
+    #[test]
+    fn display_bytes() {
+        let value = Value::Bytes(b"A\"\n\xff".to_vec());
+        assert_eq!(value.to_string(), r#"b"A\"\n\xff""#);
+    }
+}
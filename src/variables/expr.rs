@@ -0,0 +1,564 @@
+use std::iter::Peekable;
+
+use anyhow::{anyhow, bail, Result};
+
+use super::{Value, Variables};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Int(i128),
+    Str(String),
+    Bytes(Vec<u8>),
+    Ident(String),
+    Op(&'static str),
+}
+
+const OPERATORS: [&str; 16] = [
+    "<<", ">>", "+", "-", "*", "/", "%", "&", "|", "^", "~", "(", ")", "[", "]", ",",
+];
+
+/// Evaluate an expression such as `base + 0x18`, `len(payload)` or `p64(addr)`.
+///
+/// Supported functions are `len`, `hex`, `int`, `str`, `bytes`, `p8`-`p64` and `u8`-`u64`.
+pub fn evaluate(input: &str, variables: &Variables) -> Result<Value> {
+    let tokens = tokenize(input)?;
+    let mut parser = Parser {
+        tokens: tokens.into_iter().peekable(),
+        variables,
+    };
+
+    let value = parser.expr()?;
+    if let Some(token) = parser.tokens.next() {
+        bail!("Unexpected token `{token:?}` in expression.");
+    }
+    Ok(value)
+}
+
+/// Find the index of the `}` closing an interpolated expression, skipping over string literals.
+pub fn find_closing_brace(input: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut chars = input.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (Some(_), '\\') => {
+                chars.next();
+            }
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '{') => depth += 1,
+            (None, '}') if depth == 0 => return Some(i),
+            (None, '}') => depth -= 1,
+            (None, _) => {}
+        }
+    }
+
+    None
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() {
+            let mut literal = String::new();
+            while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                literal.push(c);
+            }
+            tokens.push(Token::Int(parse_int(&literal)?));
+        } else if c == '"' || c == '\'' {
+            chars.next();
+            let bytes = parse_string(&mut chars, c)?;
+            tokens.push(Token::Str(String::from_utf8(bytes).map_err(|_| {
+                anyhow!("String literals must be valid UTF-8; use a bytes literal instead.")
+            })?));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut ident = String::new();
+            while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+                ident.push(c);
+            }
+            match (ident.as_str(), chars.peek()) {
+                ("b", Some(&quote @ ('"' | '\''))) => {
+                    chars.next();
+                    tokens.push(Token::Bytes(parse_string(&mut chars, quote)?));
+                }
+                _ => tokens.push(Token::Ident(ident)),
+            }
+        } else {
+            let rest: String = chars.clone().take(2).collect();
+            let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) else {
+                bail!("Unexpected character `{c}` in expression.");
+            };
+            for _ in 0..op.len() {
+                chars.next();
+            }
+            tokens.push(Token::Op(op));
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Parse an integer literal, detecting `0x`, `0o` and `0b` prefixes.
+pub fn parse_int(literal: &str) -> Result<i128> {
+    let literal = literal.replace('_', "");
+    let (negative, literal) = match literal.strip_prefix('-') {
+        Some(literal) => (true, literal),
+        None => (false, literal.as_str()),
+    };
+
+    let (radix, digits) = match literal.get(..2).map(|prefix| prefix.to_ascii_lowercase()) {
+        Some(prefix) if prefix == "0x" => (16, &literal[2..]),
+        Some(prefix) if prefix == "0o" => (8, &literal[2..]),
+        Some(prefix) if prefix == "0b" => (2, &literal[2..]),
+        _ => (10, literal),
+    };
+
+    // `from_str_radix` would also take a sign after the prefix, e.g. `0x+1`.
+    if !digits.starts_with(|c: char| c.is_digit(radix)) {
+        bail!("Invalid integer `{literal}`.");
+    }
+    let Ok(int) = i128::from_str_radix(digits, radix) else {
+        bail!("Invalid integer `{literal}`.");
+    };
+    Ok(if negative { -int } else { int })
+}
+
+/// Add what the escape sequence `\<escaped>` stands for to `res`, taking the digits of `\xHH` and
+/// `\uHHHH` from `chars`. String literals and interpolated text share these.
+pub(crate) fn push_escape(
+    res: &mut Vec<u8>,
+    escaped: char,
+    chars: &mut impl Iterator<Item = char>,
+) -> Result<()> {
+    match escaped {
+        'n' => res.push(b'\n'),
+        'r' => res.push(b'\r'),
+        't' => res.push(b'\t'),
+        '0' => res.push(b'\0'),
+        '\\' | '#' | '\'' | '"' => res.push(escaped as u8),
+        'x' => {
+            let hex: String = chars.take(2).collect();
+            let Some(byte) = parse_hex(&hex, 2).and_then(|byte| u8::try_from(byte).ok()) else {
+                bail!("Invalid escape sequence `\\x{hex}`.");
+            };
+            res.push(byte);
+        }
+        'u' => {
+            let hex: String = chars.take(4).collect();
+            let Some(c) = parse_hex(&hex, 4).and_then(char::from_u32) else {
+                bail!("Invalid escape sequence `\\u{hex}`.");
+            };
+            res.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+        }
+        c => bail!("Unknown escape sequence `\\{c}`."),
+    }
+    Ok(())
+}
+
+/// Parse exactly `len` hex digits.
+fn parse_hex(hex: &str, len: usize) -> Option<u32> {
+    if hex.len() != len || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    u32::from_str_radix(hex, 16).ok()
+}
+
+fn parse_string<I: Iterator<Item = char>>(chars: &mut Peekable<I>, quote: char) -> Result<Vec<u8>> {
+    let mut res = Vec::new();
+
+    loop {
+        let Some(c) = chars.next() else {
+            bail!("Unterminated string literal.");
+        };
+        match c {
+            c if c == quote => return Ok(res),
+            '\\' => match chars.next() {
+                Some(escaped) => push_escape(&mut res, escaped, chars)?,
+                None => bail!("Unterminated string literal."),
+            },
+            c => res.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+}
+
+struct Parser<'a, I: Iterator<Item = Token>> {
+    tokens: Peekable<I>,
+    variables: &'a Variables,
+}
+
+impl<'a, I: Iterator<Item = Token>> Parser<'a, I> {
+    fn eat(&mut self, op: &str) -> bool {
+        self.tokens
+            .next_if(|token| matches!(token, Token::Op(o) if *o == op))
+            .is_some()
+    }
+
+    fn expect(&mut self, op: &str) -> Result<()> {
+        if !self.eat(op) {
+            bail!("Expected `{op}` in expression.");
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self) -> Result<Value> {
+        self.binary(0)
+    }
+
+    /// Parse binary operators, from loosest to tightest binding.
+    fn binary(&mut self, level: usize) -> Result<Value> {
+        const LEVELS: [&[&str]; 6] = [
+            &["|"],
+            &["^"],
+            &["&"],
+            &["<<", ">>"],
+            &["+", "-"],
+            &["*", "/", "%"],
+        ];
+
+        let Some(ops) = LEVELS.get(level) else {
+            return self.unary();
+        };
+
+        let mut lhs = self.binary(level + 1)?;
+        while let Some(op) = ops.iter().find(|op| self.eat(op)) {
+            let rhs = self.binary(level + 1)?;
+            lhs = apply(op, lhs, rhs)?;
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Value> {
+        if self.eat("-") {
+            match self.unary()? {
+                Value::Int(int) => Ok(Value::Int(int.checked_neg().ok_or_else(overflow)?)),
+                value => bail!("Cannot negate {}.", value.type_name()),
+            }
+        } else if self.eat("~") {
+            match self.unary()? {
+                Value::Int(int) => Ok(Value::Int(!int)),
+                value => bail!("Cannot invert {}.", value.type_name()),
+            }
+        } else {
+            self.postfix()
+        }
+    }
+
+    fn postfix(&mut self) -> Result<Value> {
+        let mut value = self.primary()?;
+        while self.eat("[") {
+            let index = self.expr()?;
+            self.expect("]")?;
+            value = index_into(value, index)?;
+        }
+        Ok(value)
+    }
+
+    fn primary(&mut self) -> Result<Value> {
+        let Some(token) = self.tokens.next() else {
+            bail!("Unexpected end of expression.");
+        };
+
+        match token {
+            Token::Int(int) => Ok(Value::Int(int)),
+            Token::Str(str) => Ok(Value::Str(str)),
+            Token::Bytes(bytes) => Ok(Value::Bytes(bytes)),
+            Token::Ident(name) if self.eat("(") => {
+                let args = self.list(")")?;
+                call(&name, args)
+            }
+            Token::Ident(name) => self
+                .variables
+                .get(&name)
+                .cloned()
+                .ok_or_else(|| anyhow!("Variable `{name}` is currently unset.")),
+            Token::Op("(") => {
+                let value = self.expr()?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            Token::Op("[") => Ok(Value::List(self.list("]")?)),
+            token => bail!("Unexpected token `{token:?}` in expression."),
+        }
+    }
+
+    fn list(&mut self, close: &str) -> Result<Vec<Value>> {
+        let mut items = Vec::new();
+        while !self.eat(close) {
+            items.push(self.expr()?);
+            if !self.eat(",") {
+                self.expect(close)?;
+                break;
+            }
+        }
+        Ok(items)
+    }
+}
+
+/// The longest string, bytes or list that `*` will build, so that a typo can't use up all memory.
+const MAX_REPEAT_LEN: usize = 16 * 1024 * 1024;
+
+fn overflow() -> anyhow::Error {
+    anyhow!("Integer overflow in expression.")
+}
+
+fn apply(op: &str, lhs: Value, rhs: Value) -> Result<Value> {
+    use Value::*;
+
+    let res = match (op, lhs, rhs) {
+        ("+", Int(a), Int(b)) => Int(a.checked_add(b).ok_or_else(overflow)?),
+        ("-", Int(a), Int(b)) => Int(a.checked_sub(b).ok_or_else(overflow)?),
+        ("*", Int(a), Int(b)) => Int(a.checked_mul(b).ok_or_else(overflow)?),
+        ("/", Int(a), Int(b)) => Int(a
+            .checked_div(b)
+            .ok_or_else(|| anyhow!("Division by zero."))?),
+        ("%", Int(a), Int(b)) => Int(a
+            .checked_rem(b)
+            .ok_or_else(|| anyhow!("Division by zero."))?),
+        ("&", Int(a), Int(b)) => Int(a & b),
+        ("|", Int(a), Int(b)) => Int(a | b),
+        ("^", Int(a), Int(b)) => Int(a ^ b),
+        ("<<", Int(a), Int(b)) => Int(a.checked_shl(u32::try_from(b)?).ok_or_else(overflow)?),
+        (">>", Int(a), Int(b)) => Int(a.checked_shr(u32::try_from(b)?).ok_or_else(overflow)?),
+
+        ("+", Str(a), Str(b)) => Str(a + b.as_str()),
+        ("+", Bytes(mut a), Bytes(b)) => {
+            a.extend(b);
+            Bytes(a)
+        }
+        ("+", Bytes(mut a), Str(b)) => {
+            a.extend(b.into_bytes());
+            Bytes(a)
+        }
+        ("+", Str(a), Bytes(b)) => {
+            let mut a = a.into_bytes();
+            a.extend(b);
+            Bytes(a)
+        }
+        ("+", List(mut a), List(b)) => {
+            a.extend(b);
+            List(a)
+        }
+
+        ("*", Str(a), Int(n)) | ("*", Int(n), Str(a)) => Str(a.repeat(repeat_count(a.len(), n)?)),
+        ("*", Bytes(a), Int(n)) | ("*", Int(n), Bytes(a)) => {
+            Bytes(a.repeat(repeat_count(a.len(), n)?))
+        }
+        ("*", List(a), Int(n)) | ("*", Int(n), List(a)) => {
+            let n = repeat_count(a.len(), n)?;
+            List(std::iter::repeat_n(a, n).flatten().collect())
+        }
+
+        (op, lhs, rhs) => bail!(
+            "Unsupported operation: {} {op} {}.",
+            lhs.type_name(),
+            rhs.type_name()
+        ),
+    };
+
+    Ok(res)
+}
+
+/// How many times to repeat a sequence of `len` items, if the result isn't too long.
+fn repeat_count(len: usize, n: i128) -> Result<usize> {
+    let n = usize::try_from(n)?;
+    match len.checked_mul(n) {
+        Some(total) if total <= MAX_REPEAT_LEN => Ok(n),
+        _ => bail!("Cannot repeat a sequence to more than {MAX_REPEAT_LEN} items."),
+    }
+}
+
+fn index_into(value: Value, index: Value) -> Result<Value> {
+    let Value::Int(index) = index else {
+        bail!("Cannot index with {}.", index.type_name());
+    };
+
+    let len = match &value {
+        Value::Str(str) => str.chars().count(),
+        Value::Bytes(bytes) => bytes.len(),
+        Value::List(list) => list.len(),
+        Value::Int(_) => bail!("Cannot index into int."),
+    };
+    let position = if index < 0 {
+        len as i128 + index
+    } else {
+        index
+    };
+    let Some(position) = usize::try_from(position)
+        .ok()
+        .filter(|position| *position < len)
+    else {
+        bail!("Index {index} out of range.");
+    };
+
+    Ok(match value {
+        Value::Str(str) => Value::Str(str.chars().nth(position).unwrap().to_string()),
+        Value::Bytes(bytes) => Value::Int(bytes[position].into()),
+        Value::List(mut list) => list.swap_remove(position),
+        Value::Int(_) => unreachable!(),
+    })
+}
+
+pub(crate) fn call(name: &str, args: Vec<Value>) -> Result<Value> {
+    let pack_width = |name: &str| match name {
+        "8" => Some(1),
+        "16" => Some(2),
+        "32" => Some(4),
+        "64" => Some(8),
+        _ => None,
+    };
+
+    let res = match (name, args.as_slice()) {
+        ("len", [Value::Str(str)]) => Value::Int(str.chars().count() as i128),
+        ("len", [Value::Bytes(bytes)]) => Value::Int(bytes.len() as i128),
+        ("len", [Value::List(list)]) => Value::Int(list.len() as i128),
+        ("hex", [Value::Int(int)]) if *int < 0 => Value::Str(format!("-{:#x}", int.unsigned_abs())),
+        ("hex", [Value::Int(int)]) => Value::Str(format!("{int:#x}")),
+        ("hex", [Value::Bytes(bytes)]) => {
+            Value::Str(bytes.iter().map(|byte| format!("{byte:02x}")).collect())
+        }
+        ("int", [Value::Int(int)]) => Value::Int(*int),
+        ("int", [Value::Str(str)]) => Value::Int(parse_int(str.trim())?),
+        ("int", [Value::Bytes(bytes)]) => {
+            Value::Int(parse_int(String::from_utf8_lossy(bytes).trim())?)
+        }
+        ("str", [Value::Str(str)]) => Value::Str(str.clone()),
+        ("str", [Value::Bytes(bytes)]) => Value::Str(String::from_utf8_lossy(bytes).into_owned()),
+        ("str", [value]) => Value::Str(String::from_utf8_lossy(&value.to_bytes()).into_owned()),
+        ("bytes", [Value::List(list)]) => Value::Bytes(
+            list.iter()
+                .map(|item| match item {
+                    Value::Int(int) => {
+                        u8::try_from(*int).map_err(|_| anyhow!("Byte {int} out of range."))
+                    }
+                    item => bail!("Cannot convert {} to a byte.", item.type_name()),
+                })
+                .collect::<Result<_>>()?,
+        ),
+        ("bytes", [value]) => Value::Bytes(value.to_bytes()),
+        (name, [Value::Int(int)]) if name.starts_with('p') && pack_width(&name[1..]).is_some() => {
+            let width = pack_width(&name[1..]).unwrap();
+            // Like pwntools, anything that fits as either signed or unsigned.
+            let bits = width as u32 * 8;
+            if *int < -(1 << (bits - 1)) || *int >= 1 << bits {
+                bail!("{int} doesn't fit in {width} bytes for {name}.");
+            }
+            Value::Bytes(int.to_le_bytes()[..width].to_vec())
+        }
+        (name, [Value::Bytes(bytes)])
+            if name.starts_with('u') && pack_width(&name[1..]).is_some() =>
+        {
+            let width = pack_width(&name[1..]).unwrap();
+            if bytes.len() > width {
+                bail!("Cannot unpack {} bytes with {name}.", bytes.len());
+            }
+            let mut buf = [0u8; 16];
+            buf[..bytes.len()].copy_from_slice(bytes);
+            Value::Int(i128::from_le_bytes(buf))
+        }
+        (name, args) => bail!(
+            "Unknown function `{name}({})`.",
+            args.iter()
+                .map(Value::type_name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(input: &str) -> Value {
+        let variables = Variables::from([
+            ("base".to_string(), Value::Int(0x7ffff7a00000)),
+            (
+                "leak".to_string(),
+                Value::Bytes(b"\xa0\x2a\xa6\xf7\xff\x7f".to_vec()),
+            ),
+        ]);
+        evaluate(input, &variables).unwrap()
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(eval("base + 0x18"), Value::Int(0x7ffff7a00018));
+        assert_eq!(eval("1 + 2 * 3 - -4"), Value::Int(11));
+        assert_eq!(eval("(1 + 2) * 3"), Value::Int(9));
+        assert_eq!(eval("1 << 4 | 0b1 & ~0"), Value::Int(17));
+        assert_eq!(eval("0o17 % 4"), Value::Int(3));
+    }
+
+    #[test]
+    fn packing() {
+        assert_eq!(
+            eval("p64(0x4011d6)"),
+            Value::Bytes(b"\xd6\x11\x40\0\0\0\0\0".to_vec())
+        );
+        assert_eq!(eval("p32(-1)"), Value::Bytes(b"\xff\xff\xff\xff".to_vec()));
+        assert_eq!(
+            eval("p8(0xff) + p8(-128)"),
+            Value::Bytes(b"\xff\x80".to_vec())
+        );
+        assert!(evaluate("p8(0x1337)", &Variables::new()).is_err());
+        assert!(evaluate("p8(-129)", &Variables::new()).is_err());
+        assert!(evaluate("p64(1 << 64)", &Variables::new()).is_err());
+        assert_eq!(eval("u64(leak)"), Value::Int(0x7ffff7a62aa0));
+        assert!(evaluate("u16(b'abc')", &Variables::new()).is_err());
+    }
+
+    #[test]
+    fn sequences() {
+        assert_eq!(
+            eval("b'A' * 4 + p16(0x4142)"),
+            Value::Bytes(b"AAAABA".to_vec())
+        );
+        assert_eq!(eval("len(b'A' * 40)"), Value::Int(40));
+        assert_eq!(eval("[1, 'a'][-1]"), Value::Str("a".to_string()));
+        assert_eq!(
+            eval("'héllo'[len('héllo') - 1]"),
+            Value::Str("o".to_string())
+        );
+        assert_eq!(eval("leak[0]"), Value::Int(0xa0));
+        assert_eq!(eval("hex(base)"), Value::Str("0x7ffff7a00000".to_string()));
+        assert_eq!(eval("int('0x10') + 1"), Value::Int(17));
+        assert_eq!(eval("bytes([0x41, 66])"), Value::Bytes(b"AB".to_vec()));
+        assert_eq!(
+            eval(r#"'\u00e9\'\\' + "\"""#),
+            Value::Str("é'\\\"".to_string())
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let variables = Variables::new();
+        assert!(evaluate("1 +", &variables).is_err());
+        assert!(evaluate("1 / 0", &variables).is_err());
+        assert!(evaluate("'a' - 1", &variables).is_err());
+        assert!(evaluate("unset", &variables).is_err());
+        assert!(evaluate("nope(1)", &variables).is_err());
+        assert!(evaluate("(1", &variables).is_err());
+        assert!(evaluate("0x+1", &variables).is_err());
+        assert!(evaluate("int('0x-1')", &variables).is_err());
+        assert!(evaluate("b'\\x+1'", &variables).is_err());
+        assert!(evaluate("'\\q'", &variables).is_err());
+        assert!(evaluate("'\\u+041'", &variables).is_err());
+        assert!(evaluate("'a' * 1000000000000000000", &variables).is_err());
+        assert!(evaluate("[1, 2] * (1 << 24)", &variables).is_err());
+    }
+
+    #[test]
+    fn closing_brace() {
+        assert_eq!(find_closing_brace("a + 1} rest"), Some(5));
+        assert_eq!(find_closing_brace("'}' + \"{\"}"), Some(9));
+        assert_eq!(find_closing_brace("a + 1"), None);
+    }
+}